@@ -8,8 +8,27 @@ pub const MB: u64 = 1024 * 1024;
 pub const MAX_TABLE_SIZE_MB: u64 = 256;
 pub const MIN_TABLE_SIZE_MB: u64 = 1;
 pub const DEFAULT_TABLE_SIZE_MB: u64 = 128;
-pub const TT_SIZE: u64 =
-    DEFAULT_TABLE_SIZE_MB * MB / std::mem::size_of::<rosa_lib::tt::Entry>() as u64;
+pub const MIN_THREADS: usize = 1;
+pub const MAX_THREADS: usize = 256;
+pub const DEFAULT_THREADS: usize = 1;
+pub const MIN_MULTIPV: usize = 1;
+pub const MAX_MULTIPV: usize = 256;
+pub const DEFAULT_MULTIPV: usize = 1;
+/// Millis
+pub const MIN_MOVE_OVERHEAD: u64 = 0;
+pub const MAX_MOVE_OVERHEAD: u64 = 5000;
+pub const DEFAULT_MOVE_OVERHEAD: u64 = 30;
+/// Centipawns
+pub const MIN_CONTEMPT: i32 = -100;
+pub const MAX_CONTEMPT: i32 = 100;
+pub const DEFAULT_CONTEMPT: i32 = 0;
+/// Nodes between two checks of the clock
+pub const POLL_INTERVAL: u64 = 1024;
 pub const PONDER: bool = true;
 pub const SHOW_CURRENT_LINE: bool = true;
 pub const REPORT_STATS: bool = true;
+
+/// Entries that fit into the given amount of MB (clamped to the allowed table sizes)
+pub fn tt_size(mb: u64) -> u64 {
+    mb.clamp(MIN_TABLE_SIZE_MB, MAX_TABLE_SIZE_MB) * MB / rosa_lib::tt::ENTRY_SIZE
+}
@@ -0,0 +1,168 @@
+//! # Upcoming Repetitions
+//! Repetitions are usually only detected once they happened.
+//! If the side to move can repeat a position with a single reversible move,
+//! it can at least force a draw, so alpha can be raised to the draw score before searching any move.
+//! ## Cuckoo Hashing
+//! Every reversible move (no pawns, no captures) is identified by the xor of the keys it changes:
+//! The moving piece on both squares & the side to move.
+//! All 3668 of these moves are saved in a cuckoo table with two hash functions, so a lookup takes at most two probes.
+//! Walking back the history (bounded by the last irreversible move) the xor of the current key & an earlier key
+//! has to be the key of one of these moves, which is not blocked in the current position.
+//! ## Search Tree
+//! Only repetitions of positions inside of the search tree are detected,
+//! positions before the root would need to be repeated twice (see Pos::is_repetition).
+//!
+//! Based on the paper by Marcel van Kervinck.
+
+use crate::mv::{constants, magic};
+
+use rosa_lib::piece::*;
+use rosa_lib::pos::Pos;
+use rosa_lib::tt::Key;
+
+const CUCKOO_SIZE: usize = 8192;
+const REVERSIBLE_MVS: usize = 3668;
+
+#[derive(Clone, Copy)]
+struct Entry {
+    /// 0 if the slot is empty
+    key: u64,
+    /// Squares that have to be empty for the move to be possible
+    between: u64,
+}
+
+impl Entry {
+    const EMPTY: Entry = Entry { key: 0, between: 0 };
+}
+
+static mut TABLE: [Entry; CUCKOO_SIZE] = [Entry::EMPTY; CUCKOO_SIZE];
+
+#[inline(always)]
+fn h1(key: u64) -> usize {
+    key as usize & (CUCKOO_SIZE - 1)
+}
+
+#[inline(always)]
+fn h2(key: u64) -> usize {
+    (key >> 16) as usize & (CUCKOO_SIZE - 1)
+}
+
+/// Needs the zobrist keys & the magics
+pub fn init_cuckoo() {
+    let mut table = [Entry::EMPTY; CUCKOO_SIZE];
+    let mut count = 0;
+    let pieces = [
+        Piece::Knight,
+        Piece::Bishop,
+        Piece::Rook,
+        Piece::Queen,
+        Piece::King,
+    ];
+
+    for piece in pieces
+        .iter()
+        .flat_map(|p| [p.clr(Clr::White), p.clr(Clr::Black)])
+    {
+        for s1 in 0..64 {
+            for s2 in s1 + 1..64 {
+                if attacks(piece, s1) & (1 << s2) == 0 {
+                    continue;
+                }
+                let mut entry = Entry {
+                    key: mv_key(piece, s1, s2),
+                    between: between(s1, s2),
+                };
+                // Kick out the entry in the slot & move it to its other slot, until an empty one is found
+                let mut i = h1(entry.key);
+                loop {
+                    std::mem::swap(&mut table[i], &mut entry);
+                    if entry.key == 0 {
+                        break;
+                    }
+                    i = if i == h1(entry.key) {
+                        h2(entry.key)
+                    } else {
+                        h1(entry.key)
+                    };
+                }
+                count += 1;
+            }
+        }
+    }
+    debug_assert_eq!(count, REVERSIBLE_MVS);
+    // Only called once on init
+    unsafe { TABLE = table };
+}
+
+/// The side to move can reach a position of the search tree with a single move
+/// ply: Distance from the root
+pub fn upcoming_repetition(p: &Pos, ply: usize) -> bool {
+    let end = p.reversible_plies();
+    if end < 3 {
+        return false;
+    }
+
+    let side = side_key();
+    let key = p.key().val();
+    // Xor of the moves of the opponent, they have to cancel out
+    let mut other = key ^ p.key_before(1).val() ^ side;
+    for plies in (3..=end).step_by(2) {
+        other ^= p.key_before(plies - 1).val() ^ p.key_before(plies).val() ^ side;
+        if other != 0 {
+            continue;
+        }
+
+        let mv_key = key ^ p.key_before(plies).val();
+        if let Some(entry) = lookup(mv_key)
+            && entry.between & p.full().val() == 0
+            && plies < ply
+        {
+            return true;
+        }
+    }
+    false
+}
+
+#[inline(always)]
+fn lookup(mv_key: u64) -> Option<Entry> {
+    [h1(mv_key), h2(mv_key)]
+        .into_iter()
+        // Only written on init
+        .map(|i| unsafe { TABLE[i] })
+        .find(|entry| entry.key == mv_key)
+}
+
+fn side_key() -> u64 {
+    let mut key = Key::new_from(0);
+    key.color();
+    key.val()
+}
+
+fn mv_key(piece: ClrPiece, s1: u8, s2: u8) -> u64 {
+    let mut key = Key::new_from(side_key());
+    key.piece(s1, piece);
+    key.piece(s2, piece);
+    key.val()
+}
+
+/// Attacks on an empty board
+fn attacks(piece: ClrPiece, sq: u8) -> u64 {
+    match piece.de_clr() {
+        Piece::Bishop => magic::bishop_attacks(sq, 0),
+        Piece::Rook => magic::rook_attacks(sq, 0),
+        Piece::Queen => magic::bishop_attacks(sq, 0) | magic::rook_attacks(sq, 0),
+        _ => constants::get_mask(piece, sq),
+    }
+}
+
+/// Squares strictly between two aligned squares, 0 otherwise
+fn between(s1: u8, s2: u8) -> u64 {
+    let (b1, b2) = (1 << s1, 1 << s2);
+    if magic::rook_attacks(s1, 0) & b2 != 0 {
+        magic::rook_attacks(s1, b2) & magic::rook_attacks(s2, b1)
+    } else if magic::bishop_attacks(s1, 0) & b2 != 0 {
+        magic::bishop_attacks(s1, b2) & magic::bishop_attacks(s2, b1)
+    } else {
+        0
+    }
+}
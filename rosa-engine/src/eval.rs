@@ -5,9 +5,6 @@
 
 use rosa_lib::pos;
 
-pub const SAFE_MAX_SCORE: i32 = i32::MAX;
-pub const SAFE_MIN_SCORE: i32 = i32::MIN + 1;
-
 // Stolen from: https://www.chessprogramming.org/Tapered_Eval
 pub fn eval(p: &pos::Pos) -> i32 {
     let mut middelgame = 0;
@@ -27,11 +24,23 @@ pub fn eval(p: &pos::Pos) -> i32 {
     (((middelgame * (256 - phase)) + endgame * phase) / 256) * p.clr().as_sign() as i32
 }
 
+/// 0 at the start of the game, ENDGAME_PHASE once only pawns & kings are left
+pub fn phase(p: &pos::Pos) -> i32 {
+    let material: i32 = p
+        .piece_iter()
+        .flatten()
+        .map(|piece| PHASEARRAY[piece.index()])
+        .sum();
+    let phase = (STARTPHASE - material).max(0);
+    (phase * ENDGAME_PHASE + (STARTPHASE / 2)) / STARTPHASE
+}
+
 static mut MIDDLEGAME_TABLE: [[i32; 64]; 12] = [[0; 64]; 12];
 static mut ENDGAME_TABLE: [[i32; 64]; 12] = [[0; 64]; 12];
 
 const PHASEARRAY: [i32; 12] = [0, 1, 1, 2, 4, 0, 0, 1, 1, 2, 4, 0];
 const STARTPHASE: i32 = 24;
+pub const ENDGAME_PHASE: i32 = 256;
 
 pub fn init_eval() {
     unsafe {
@@ -1,8 +1,11 @@
 //! # Generating Positions from FEN strings
-//! Not designed to be robust
-//! If you pass this a wrong FEN it WILL do bullshit
+//! fen() is not designed to be robust
+//! If you pass it a wrong FEN it WILL do bullshit
+//! ## User Input
+//! try_fen() checks the FEN & the moves first, so the position can be searched safely
 
 use crate::make;
+use crate::search;
 
 use rosa_lib::mv::Mv;
 use rosa_lib::piece::*;
@@ -87,8 +90,11 @@ pub fn fen(fen: Vec<&str>, moves: Vec<&str>) -> pos::Pos {
         ep_file = file as u8 - b'a';
     }
 
-    // split_fen[4] and 5 specify move clocks, which we dont use yet
     let mut pos = pos::Pos::new(sq, clr, is_ep, ep_file, pos::Castling { wk, wq, bk, bq });
+    // split_fen[5] (the fullmove number) is not used
+    if let Some(halfmove_clock) = fen.get(4).and_then(|clock| clock.parse().ok()) {
+        pos.set_halfmove_clock(halfmove_clock);
+    }
 
     for mv in moves {
         let mut mv = Mv::new_from_str(mv, &pos);
@@ -97,3 +103,109 @@ pub fn fen(fen: Vec<&str>, moves: Vec<&str>) -> pos::Pos {
 
     pos
 }
+
+/// For FENs & moves from the gui, the halfmove clock & fullmove number are optional
+pub fn try_fen(fen: Vec<&str>, moves: Vec<&str>) -> Result<pos::Pos, String> {
+    validate(&fen)?;
+    let mut pos = self::fen(fen, Vec::new());
+    // The side that just moved cant have left its king in check
+    let mut last_mover = pos.clone();
+    last_mover.flip_color();
+    if make::king_in_check(&last_mover) {
+        return Err("the side not to move is in check".to_string());
+    }
+
+    for mv_str in moves {
+        let Some(mut mv) = find_mv(&mut pos, mv_str) else {
+            return Err(format!("illegal move {mv_str}"));
+        };
+        make::unchecked_make(&mut pos, &mut mv);
+    }
+    Ok(pos)
+}
+
+/// The legal move with this uci notation
+pub fn find_mv(p: &mut pos::Pos, mv_str: &str) -> Option<Mv> {
+    search::legal_mvs(p)
+        .into_iter()
+        .find(|mv| mv.to_string() == mv_str)
+}
+
+/// Everything fen() relies on
+fn validate(fen: &[&str]) -> Result<(), String> {
+    let [board, clr, castle, ep, ..] = fen else {
+        return Err(format!("fen needs at least 4 fields, got {}", fen.len()));
+    };
+
+    let ranks: Vec<&str> = board.rsplit('/').collect();
+    if ranks.len() != 8 {
+        return Err(format!("fen needs 8 ranks, got {}", ranks.len()));
+    }
+    let mut sq = [' '; 64];
+    for (rank, rank_str) in ranks.iter().enumerate() {
+        let mut file = 0;
+        for piece in rank_str.chars() {
+            match piece {
+                '1'..='8' => file += piece.to_digit(10).unwrap() as usize,
+                'P' | 'N' | 'B' | 'R' | 'Q' | 'K' | 'p' | 'n' | 'b' | 'r' | 'q' | 'k' => {
+                    if file < 8 {
+                        sq[rank * 8 + file] = piece;
+                    }
+                    file += 1;
+                }
+                _ => return Err(format!("invalid piece {piece}")),
+            }
+        }
+        if file != 8 {
+            return Err(format!("rank {} has {file} squares", rank + 1));
+        }
+    }
+
+    for king in ['K', 'k'] {
+        let count = sq.iter().filter(|piece| **piece == king).count();
+        if count != 1 {
+            return Err(format!("fen needs exactly one {king}, got {count}"));
+        }
+    }
+    let back_ranks = sq[..8].iter().chain(&sq[56..]);
+    if back_ranks
+        .copied()
+        .any(|piece| piece == 'P' || piece == 'p')
+    {
+        return Err("pawns on the first or last rank".to_string());
+    }
+
+    if *clr != "w" && *clr != "b" {
+        return Err(format!("invalid color {clr}"));
+    }
+
+    if *castle != "-" {
+        for right in castle.chars() {
+            // King square, rook square
+            let (king, rook) = match right {
+                'K' => (4, 7),
+                'Q' => (4, 0),
+                'k' => (60, 63),
+                'q' => (60, 56),
+                _ => return Err(format!("invalid castling right {right}")),
+            };
+            let pieces = match right.is_ascii_uppercase() {
+                true => ('K', 'R'),
+                false => ('k', 'r'),
+            };
+            if (sq[king], sq[rook]) != pieces {
+                return Err(format!("castling right {right} without king & rook"));
+            }
+        }
+    }
+
+    let ep_valid = match ep.as_bytes() {
+        b"-" => true,
+        [file, rank] => (b'a'..=b'h').contains(file) && (*rank == b'3' || *rank == b'6'),
+        _ => false,
+    };
+    if !ep_valid {
+        return Err(format!("invalid en passant square {ep}"));
+    }
+    Ok(())
+}
@@ -6,12 +6,16 @@
 #![deny(unused_must_use)]
 
 pub mod config;
+pub mod cuckoo;
 pub mod eval;
 pub mod fen;
 pub mod make;
 pub mod mv;
+pub mod params;
+pub mod quiscence;
 pub mod runtime;
 pub mod search;
+pub mod stack;
 pub mod thread_search;
 pub mod time;
-pub mod quiscence;
\ No newline at end of file
+pub mod uci;
@@ -159,7 +159,8 @@ pub fn unchecked_make(p: &mut Pos, mv: &mut Mv) {
     p.set_castling(castle);
     p.set_ep(ep);
 
-    p.repetition.push(p.key());
+    let irreversible = mv.is_cap() || piece.de_clr() == Piece::Pawn || mv.is_prom();
+    p.push_history(irreversible);
 }
 
 pub fn unmake(p: &mut Pos, mv: Mv, guard: MakeGuard) {
@@ -173,7 +174,7 @@ pub fn unmake(p: &mut Pos, mv: Mv, guard: MakeGuard) {
 
     p.flip_color();
     p.piece_toggle(piece, end);
-    p.repetition.pop();
+    p.pop_history();
 
     match mv.flag() {
         Flag::Quiet | Flag::Cap | Flag::Double => {}
@@ -228,7 +229,8 @@ pub fn make_null(p: &mut Pos) -> (Legal, Option<u8>, MakeGuard) {
     let was_ep = p.ep();
     p.set_ep(None);
     p.flip_color();
-    p.repetition.push(p.key());
+    // Repetitions across a null move are not real
+    p.push_history(true);
 
     let legal = if square_attacked(p, color, king_pos) {
         Legal::ILLEGAL
@@ -243,7 +245,14 @@ pub fn unmake_null(p: &mut Pos, was_ep: Option<u8>, guard: MakeGuard) {
     guard.verified_drop();
     p.flip_color();
     p.set_ep(was_ep);
-    p.repetition.pop();
+    p.pop_history();
+}
+
+/// The side to move is in check
+#[inline(always)]
+pub fn king_in_check(p: &Pos) -> bool {
+    let king_pos = p.piece(Piece::King.clr(p.clr())).get_ones_single();
+    square_attacked(p, p.clr(), king_pos)
 }
 
 /// Basically we pretend there is every possible piece on the square
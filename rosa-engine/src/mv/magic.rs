@@ -9,13 +9,8 @@ pub fn queen_mask(sq: u8, p: &Pos, cap: bool) -> u64 {
 }
 
 pub fn rook_mask(sq: u8, p: &Pos, cap: bool) -> u64 {
-    let sq = sq as usize;
-    let premask = unsafe { ROOK_PREMASKS_TRUNC[sq] };
-    let magic = ROOK_MAGIC[sq];
-    let shift = ROOK_SHIFT[sq];
-    let blocker = premask & p.full().val();
-    let index = magic_index(magic, shift, blocker);
-    let res = unsafe { ROOK_LOOKUP[sq][index] };
+    let blocker = unsafe { ROOK_PREMASKS_TRUNC[sq as usize] } & p.full().val();
+    let res = rook_attacks(sq, p.full().val());
     // Since our blockers dont extend to the outside squares
     // -> Since it doesnt matter if there is a piece there
     // However it does matter for captures
@@ -27,13 +22,8 @@ pub fn rook_mask(sq: u8, p: &Pos, cap: bool) -> u64 {
 }
 
 pub fn bishop_mask(sq: u8, p: &Pos, cap: bool) -> u64 {
-    let sq = sq as usize;
-    let premask = unsafe { BISHOP_PREMASKS_TRUNC[sq] };
-    let magic = BISHOP_MAGIC[sq];
-    let shift = BISHOP_SHIFT[sq];
-    let blocker = premask & p.full().val();
-    let index = magic_index(magic, shift, blocker);
-    let res = unsafe { BISHOP_LOOKUP[sq][index] };
+    let blocker = unsafe { BISHOP_PREMASKS_TRUNC[sq as usize] } & p.full().val();
+    let res = bishop_attacks(sq, p.full().val());
     if cap {
         res & (blocker | OUTSIDE_RANK_MASK)
     } else {
@@ -41,6 +31,22 @@ pub fn bishop_mask(sq: u8, p: &Pos, cap: bool) -> u64 {
     }
 }
 
+/// All squares a rook on sq attacks with the given occupancy (including the blocking squares)
+pub fn rook_attacks(sq: u8, occupied: u64) -> u64 {
+    let sq = sq as usize;
+    let premask = unsafe { ROOK_PREMASKS_TRUNC[sq] };
+    let index = magic_index(ROOK_MAGIC[sq], ROOK_SHIFT[sq], premask & occupied);
+    unsafe { ROOK_LOOKUP[sq][index] }
+}
+
+/// All squares a bishop on sq attacks with the given occupancy (including the blocking squares)
+pub fn bishop_attacks(sq: u8, occupied: u64) -> u64 {
+    let sq = sq as usize;
+    let premask = unsafe { BISHOP_PREMASKS_TRUNC[sq] };
+    let index = magic_index(BISHOP_MAGIC[sq], BISHOP_SHIFT[sq], premask & occupied);
+    unsafe { BISHOP_LOOKUP[sq][index] }
+}
+
 pub fn magic_index(magic: u64, shift: u8, blocker: u64) -> usize {
     (u64::wrapping_mul(magic, blocker) >> (64 - shift)) as usize
 }
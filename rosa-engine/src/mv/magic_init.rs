@@ -103,11 +103,7 @@ fn init_lookups() {
 }
 
 pub fn gen_move_mask(
-    sq: usize,
-    directions: &[i8],
-    iterations: i8,
-    blocker_mask: u64,
-    truncate: bool,
+    sq: usize, directions: &[i8], iterations: i8, blocker_mask: u64, truncate: bool,
 ) -> u64 {
     let mut pos_moves = Vec::new();
     'direction: for dir in directions {
@@ -2,7 +2,9 @@
 //! Submodule
 
 pub mod constants;
+pub mod gen_magics;
 pub mod magic;
 pub mod magic_init;
 pub mod mv_gen;
-pub mod gen_magics;
+pub mod picker;
+pub mod see;
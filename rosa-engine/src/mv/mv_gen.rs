@@ -1,8 +1,8 @@
 //! # Move Generation
 //! Since move generation has to be done at every node (except if we find a good TT move/ The null move or TT Move produce a cutoff),
 //! it has to be quite optimized. Move generation uses several optimizations techniques, most notably magic bitboards.
-//! Since for most nodes we dont actually use most moves, move generation is commonly done in stages
-//! (Currently Cap + Non Cap, Possibly Promotions in the future)
+//! Since for most nodes we dont actually use most moves, move generation is done in stages
+//! (Prom Caps, Proms, Caps, Quiets), which the move picker only generates once they are reached
 //! ## Legal Moves
 //! Rosas move generation functions generates pseudo-legal moves i.e. legal moves that dont check if they leave the king in check.
 //! The legality is only checked inside of make() using square_not_attacked().
@@ -13,19 +13,98 @@ use super::constants;
 use super::magic;
 
 use rosa_lib::board::Board;
-use rosa_lib::mv::Mv;
+use rosa_lib::mv::{Flag, Mv};
 use rosa_lib::piece::*;
 use rosa_lib::pos::Pos;
 use rosa_lib::util;
 
-use std::collections::BinaryHeap;
+/// Upper bound for the amount of pseudo legal moves in a position (The maximum for legal moves is 218)
+pub const MAX_MVS: usize = 256;
 
-pub fn gen_mvs(p: &Pos) -> BinaryHeap<Mv> {
-    let mut heap = gen_mvs_stages(p, MvGenStage::Quite);
-    heap.append(&mut gen_mvs_stages(p, MvGenStage::Cap));
-    heap.append(&mut gen_mvs_stages(p, MvGenStage::Prom));
-    heap.append(&mut gen_mvs_stages(p, MvGenStage::PromCap));
-    heap
+/// Fixed size move list, so move generation does not allocate
+#[derive(Clone)]
+pub struct MvList {
+    mvs: [Mv; MAX_MVS],
+    len: usize,
+}
+
+impl Default for MvList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MvList {
+    pub fn new() -> MvList {
+        MvList {
+            mvs: [Mv::null(); MAX_MVS],
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, mv: Mv) {
+        debug_assert!(self.len < MAX_MVS);
+        self.mvs[self.len] = mv;
+        self.len += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<Mv> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        Some(self.mvs[self.len])
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn as_slice(&self) -> &[Mv] {
+        &self.mvs[..self.len]
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [Mv] {
+        &mut self.mvs[..self.len]
+    }
+
+    pub fn contains(&self, mv: &Mv) -> bool {
+        self.as_slice().iter().any(|m| m.fuzzy_compare(mv))
+    }
+}
+
+impl Extend<Mv> for MvList {
+    fn extend<T: IntoIterator<Item = Mv>>(&mut self, iter: T) {
+        for mv in iter {
+            self.push(mv);
+        }
+    }
+}
+
+impl IntoIterator for MvList {
+    type Item = Mv;
+    type IntoIter = std::iter::Take<std::array::IntoIter<Mv, MAX_MVS>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.mvs.into_iter().take(self.len)
+    }
+}
+
+pub fn gen_mvs(p: &Pos) -> MvList {
+    let mut mvs = MvList::new();
+    gen_mvs_into(p, MvGenStage::Quite, &mut mvs);
+    gen_mvs_into(p, MvGenStage::Cap, &mut mvs);
+    gen_mvs_into(p, MvGenStage::Prom, &mut mvs);
+    gen_mvs_into(p, MvGenStage::PromCap, &mut mvs);
+    mvs
 }
 
 pub enum MvGenStage {
@@ -35,63 +114,98 @@ pub enum MvGenStage {
     Quite,
 }
 
-pub fn gen_mvs_iter(p: &Pos) -> Box<dyn Iterator<Item = Mv>> {
-    let iter = gen_mvs_stages(p, MvGenStage::PromCap)
-        .into_iter()
-        .chain(gen_mvs_stages(p, MvGenStage::Prom))
-        .chain(gen_mvs_stages(p, MvGenStage::Cap))
-        .chain(gen_mvs_stages(p, MvGenStage::Quite));
-    Box::new(iter)
+/// Unordered, for ordered moves use the MovePicker
+pub fn gen_mvs_iter(p: &Pos) -> impl Iterator<Item = Mv> + use<> {
+    gen_mvs(p).into_iter()
 }
 
-pub fn pv_gen_mvs_iter(p: &Pos, pv: Option<Mv>) -> Box<dyn Iterator<Item = Mv>> {
-    match pv {
-        None => gen_mvs_iter(p),
-        Some(pv_move) => {
-            let iter = gen_mvs_stages(p, MvGenStage::PromCap)
-                .into_iter()
-                .chain(gen_mvs_stages(p, MvGenStage::Prom))
-                .chain(gen_mvs_stages(p, MvGenStage::Cap))
-                .chain(gen_mvs_stages(p, MvGenStage::Quite))
-                .filter(move |mv| mv != &pv_move);
-            Box::new(iter)
-        }
-    }
+pub fn gen_mvs_stages(p: &Pos, stage: MvGenStage) -> MvList {
+    let mut mvs = MvList::new();
+    gen_mvs_into(p, stage, &mut mvs);
+    mvs
 }
 
-pub fn gen_mvs_stages(p: &Pos, stage: MvGenStage) -> BinaryHeap<Mv> {
-    let mut mvs = BinaryHeap::with_capacity(8);
+pub fn gen_mvs_into(p: &Pos, stage: MvGenStage, mvs: &mut MvList) {
     match stage {
         MvGenStage::PromCap => {
-            gen_prom(p, &mut mvs, true);
+            gen_prom(p, mvs, true);
         }
         MvGenStage::Prom => {
-            gen_prom(p, &mut mvs, false);
+            gen_prom(p, mvs, false);
         }
         MvGenStage::Cap => {
-            gen_ep(p, &mut mvs);
-            gen_mv_from_piece(p, &mut mvs, Piece::Knight, true);
-            gen_mv_from_piece(p, &mut mvs, Piece::Bishop, true);
-            gen_mv_from_piece(p, &mut mvs, Piece::Rook, true);
-            gen_mv_from_piece(p, &mut mvs, Piece::Queen, true);
-            gen_mv_from_piece(p, &mut mvs, Piece::King, true);
-            gen_mv_from_piece(p, &mut mvs, Piece::Pawn, true);
+            gen_ep(p, mvs);
+            gen_mv_from_piece(p, mvs, Piece::Knight, true);
+            gen_mv_from_piece(p, mvs, Piece::Bishop, true);
+            gen_mv_from_piece(p, mvs, Piece::Rook, true);
+            gen_mv_from_piece(p, mvs, Piece::Queen, true);
+            gen_mv_from_piece(p, mvs, Piece::King, true);
+            gen_mv_from_piece(p, mvs, Piece::Pawn, true);
         }
         MvGenStage::Quite => {
+            gen_pawn_double(p, mvs);
+            gen_mv_from_piece(p, mvs, Piece::Knight, false);
+            gen_mv_from_piece(p, mvs, Piece::Bishop, false);
+            gen_mv_from_piece(p, mvs, Piece::Rook, false);
+            gen_mv_from_piece(p, mvs, Piece::Queen, false);
+            gen_mv_from_piece(p, mvs, Piece::King, false);
+            gen_mv_from_piece(p, mvs, Piece::Pawn, false);
+            gen_castle(p, mvs);
+        }
+    }
+}
+
+/// Checks if a move that was not generated in this position (TT move, killers, counter moves)
+/// is a pseudo legal move in this position.
+/// Quiets & captures are checked directly, the rare move types are generated and compared
+pub fn is_pseudo_legal(p: &Pos, mv: &Mv) -> bool {
+    if mv.is_null() {
+        return false;
+    }
+
+    let (start, end) = mv.sq();
+    let piece = match p.piece_at_sq(start) {
+        Some(piece) if piece.clr() == p.clr() => piece,
+        _ => return false,
+    };
+
+    let mut mvs = MvList::new();
+    match mv.flag() {
+        Flag::Quiet => p.board_empty_at(end) && get_movemask(p, piece, start, false).at(end),
+        Flag::Cap => match p.piece_at_sq(end) {
+            Some(victim) => {
+                victim.clr() != p.clr()
+                    && victim.de_clr() == mv.cap_victim()
+                    && piece.de_clr() == mv.cap_capturer()
+                    && get_movemask(p, piece, start, true).at(end)
+            }
+            None => false,
+        },
+        Flag::Double => {
             gen_pawn_double(p, &mut mvs);
-            gen_mv_from_piece(p, &mut mvs, Piece::Knight, false);
-            gen_mv_from_piece(p, &mut mvs, Piece::Bishop, false);
-            gen_mv_from_piece(p, &mut mvs, Piece::Rook, false);
-            gen_mv_from_piece(p, &mut mvs, Piece::Queen, false);
-            gen_mv_from_piece(p, &mut mvs, Piece::King, false);
-            gen_mv_from_piece(p, &mut mvs, Piece::Pawn, false);
+            mvs.contains(mv)
+        }
+        Flag::Ep => {
+            gen_ep(p, &mut mvs);
+            mvs.contains(mv)
+        }
+        Flag::WKC | Flag::WQC | Flag::BKC | Flag::BQC => {
             gen_castle(p, &mut mvs);
+            mvs.contains(mv)
+        }
+        Flag::Prom => {
+            gen_prom(p, &mut mvs, false);
+            mvs.contains(mv)
+        }
+        Flag::PromCap => {
+            gen_prom(p, &mut mvs, true);
+            // The victim is not part of the fuzzy compare
+            mvs.contains(mv) && p.piece_at_sq(end).map(|v| v.de_clr()) == Some(mv.cap_victim())
         }
     }
-    mvs
 }
 
-fn gen_mv_from_piece(p: &Pos, mvs: &mut BinaryHeap<Mv>, piece: Piece, cap: bool) {
+fn gen_mv_from_piece(p: &Pos, mvs: &mut MvList, piece: Piece, cap: bool) {
     let piece = piece.clr(p.clr());
     let piece_positions = p.piece(piece).get_ones();
     for sq in piece_positions {
@@ -105,7 +219,7 @@ fn gen_mv_from_piece(p: &Pos, mvs: &mut BinaryHeap<Mv>, piece: Piece, cap: bool)
                     }
                 }
                 (false, None) => {
-                    mvs.push(Mv::new_quiet(sq, end_square, p.clr()));
+                    mvs.push(Mv::new_quiet(sq, end_square));
                 }
                 _ => (),
             }
@@ -131,7 +245,7 @@ fn get_movemask(p: &Pos, piece: ClrPiece, sq: u8, cap: bool) -> Board {
     Board::new_from(raw_board)
 }
 
-fn gen_prom(p: &Pos, mvs: &mut BinaryHeap<Mv>, cap: bool) {
+fn gen_prom(p: &Pos, mvs: &mut MvList, cap: bool) {
     let rank = if p.clr().is_white() { 6 } else { 1 };
     let pawn_bb = p.piece(Piece::Pawn.clr(p.clr()));
     // Only pawns that are on the last rank
@@ -163,7 +277,7 @@ fn gen_prom(p: &Pos, mvs: &mut BinaryHeap<Mv>, cap: bool) {
     }
 }
 
-fn gen_ep(p: &Pos, mvs: &mut BinaryHeap<Mv>) {
+fn gen_ep(p: &Pos, mvs: &mut MvList) {
     match p.ep() {
         None => (),
         Some(file) => {
@@ -197,7 +311,7 @@ fn gen_ep(p: &Pos, mvs: &mut BinaryHeap<Mv>) {
     }
 }
 
-fn gen_castle(p: &Pos, mvs: &mut BinaryHeap<Mv>) {
+fn gen_castle(p: &Pos, mvs: &mut MvList) {
     let king_bb = p.piece(Piece::King.clr(p.clr()));
     let king_pos = king_bb.get_ones_single();
     let castle = p.castle();
@@ -232,7 +346,7 @@ fn gen_castle(p: &Pos, mvs: &mut BinaryHeap<Mv>) {
     }
 }
 
-fn gen_pawn_double(p: &Pos, mvs: &mut BinaryHeap<Mv>) {
+fn gen_pawn_double(p: &Pos, mvs: &mut MvList) {
     let bb = p.piece(Piece::Pawn.clr(p.clr()));
     let rank = if p.clr().is_white() { 1 } else { 6 };
 
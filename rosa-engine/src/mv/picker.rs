@@ -0,0 +1,245 @@
+//! # Move Picker
+//! Most nodes in the search tree never look at most of their moves, since the first few already produce a cutoff.
+//! Instead of generating & sorting all moves up front, the move picker is a state machine that
+//! only generates the next group of moves once all moves of the current group were tried.
+//! ## Stages
+//! 1. TT move (validated, since it could be from a different position with the same index)
+//! 2. Good captures & promotions (SEE >= 0), ordered by victim value & capture history
+//! 3. Killer moves
+//! 4. Counter move
+//! 5. Quiet moves, ordered by butterfly & continuation history
+//! 6. Bad captures (SEE < 0)
+//!
+//! Moves are picked by selection sort, since we usually only need the first few.
+//! ## Quiscence Search
+//! Only the TT move & good captures / promotions. Quiet moves (to look for checks) are optional,
+//! killers, counter move & bad captures are skipped.
+
+use super::mv_gen::{self, MAX_MVS, MvGenStage, MvList};
+use super::see;
+
+use rosa_lib::history;
+use rosa_lib::history::PieceTo;
+use rosa_lib::mv::Mv;
+use rosa_lib::piece::{ClrPiece, Piece};
+use rosa_lib::pos::Pos;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum Stage {
+    TtMv,
+    GenNoisy,
+    GoodNoisy,
+    Killer1,
+    Killer2,
+    Counter,
+    GenQuiet,
+    Quiet,
+    BadNoisy,
+    Done,
+}
+
+pub struct MovePicker {
+    stage: Stage,
+    tt_mv: Mv,
+    killers: [Mv; 2],
+    counter: Mv,
+    cont: [Option<PieceTo>; 2],
+    /// Skip refutations & bad captures (quiscence search)
+    noisy_only: bool,
+    /// Quiet moves are always generated outside of the quiscence search
+    quiets: bool,
+
+    mvs: MvList,
+    scores: [i32; MAX_MVS],
+    index: usize,
+    bad_noisy: MvList,
+    bad_index: usize,
+}
+
+impl MovePicker {
+    /// cont: The last two moves for the continuation history
+    pub fn new(
+        tt_mv: Option<Mv>, killers: [Mv; 2], counter: Mv, cont: [Option<PieceTo>; 2],
+    ) -> MovePicker {
+        MovePicker {
+            stage: Stage::TtMv,
+            tt_mv: tt_mv.unwrap_or(Mv::null()),
+            killers,
+            counter,
+            cont,
+            noisy_only: false,
+            quiets: true,
+            mvs: MvList::new(),
+            scores: [0; MAX_MVS],
+            index: 0,
+            bad_noisy: MvList::new(),
+            bad_index: 0,
+        }
+    }
+
+    /// Only good captures & promotions, ordered the same way as in the normal search
+    /// The tt move is returned first, even if it is quiet
+    /// quiets: Return quiet moves after the captures
+    pub fn new_noisy(tt_mv: Option<Mv>, quiets: bool) -> MovePicker {
+        let mut picker = MovePicker::new(tt_mv, [Mv::null(); 2], Mv::null(), [None; 2]);
+        picker.noisy_only = true;
+        picker.quiets = quiets;
+        picker
+    }
+
+    /// Moves returned are pseudo legal & every move is returned at most once
+    pub fn next(&mut self, p: &Pos) -> Option<Mv> {
+        loop {
+            match self.stage {
+                Stage::TtMv => {
+                    self.stage = Stage::GenNoisy;
+                    if mv_gen::is_pseudo_legal(p, &self.tt_mv) {
+                        return Some(self.tt_mv);
+                    }
+                    self.tt_mv = Mv::null();
+                }
+                Stage::GenNoisy => {
+                    self.mvs.clear();
+                    self.index = 0;
+                    mv_gen::gen_mvs_into(p, MvGenStage::PromCap, &mut self.mvs);
+                    mv_gen::gen_mvs_into(p, MvGenStage::Prom, &mut self.mvs);
+                    mv_gen::gen_mvs_into(p, MvGenStage::Cap, &mut self.mvs);
+                    for (i, mv) in self.mvs.as_slice().iter().enumerate() {
+                        self.scores[i] = noisy_score(p, mv);
+                    }
+                    self.stage = Stage::GoodNoisy;
+                }
+                Stage::GoodNoisy => match self.pick_best() {
+                    Some(mv) if mv.fuzzy_compare(&self.tt_mv) => {}
+                    Some(mv) => {
+                        if see::see(p, &mv) >= 0 {
+                            return Some(mv);
+                        }
+                        self.bad_noisy.push(mv);
+                    }
+                    None => {
+                        self.stage = match (self.noisy_only, self.quiets) {
+                            (false, _) => Stage::Killer1,
+                            (true, true) => Stage::GenQuiet,
+                            (true, false) => Stage::Done,
+                        };
+                    }
+                },
+                Stage::Killer1 => {
+                    self.stage = Stage::Killer2;
+                    let killer = self.killers[0];
+                    if self.is_new_quiet(p, &killer, 0) {
+                        return Some(killer);
+                    }
+                    self.killers[0] = Mv::null();
+                }
+                Stage::Killer2 => {
+                    self.stage = Stage::Counter;
+                    let killer = self.killers[1];
+                    if self.is_new_quiet(p, &killer, 1) {
+                        return Some(killer);
+                    }
+                    self.killers[1] = Mv::null();
+                }
+                Stage::Counter => {
+                    self.stage = Stage::GenQuiet;
+                    let counter = self.counter;
+                    if self.is_new_quiet(p, &counter, 2) {
+                        return Some(counter);
+                    }
+                    self.counter = Mv::null();
+                }
+                Stage::GenQuiet => {
+                    self.mvs.clear();
+                    self.index = 0;
+                    mv_gen::gen_mvs_into(p, MvGenStage::Quite, &mut self.mvs);
+                    for (i, mv) in self.mvs.as_slice().iter().enumerate() {
+                        self.scores[i] = history::quiet_score(mv, moving_piece(p, mv), &self.cont);
+                    }
+                    self.stage = Stage::Quiet;
+                }
+                Stage::Quiet => match self.pick_best() {
+                    Some(mv) if self.already_returned(&mv) => {}
+                    Some(mv) => return Some(mv),
+                    None => {
+                        self.stage = if self.noisy_only {
+                            Stage::Done
+                        } else {
+                            Stage::BadNoisy
+                        };
+                    }
+                },
+                Stage::BadNoisy => {
+                    if self.bad_index < self.bad_noisy.len() {
+                        self.bad_index += 1;
+                        return Some(self.bad_noisy.as_slice()[self.bad_index - 1]);
+                    }
+                    self.stage = Stage::Done;
+                }
+                Stage::Done => return None,
+            }
+        }
+    }
+
+    /// Selection sort step: swaps the best remaining move to the front
+    fn pick_best(&mut self) -> Option<Mv> {
+        if self.index >= self.mvs.len() {
+            return None;
+        }
+
+        let mut best = self.index;
+        for i in self.index + 1..self.mvs.len() {
+            if self.scores[i] > self.scores[best] {
+                best = i;
+            }
+        }
+
+        self.mvs.as_mut_slice().swap(self.index, best);
+        self.scores.swap(self.index, best);
+        self.index += 1;
+        Some(self.mvs.as_slice()[self.index - 1])
+    }
+
+    /// Refutations (killers & counter) are only valid, if they are quiet & pseudo legal in this position
+    fn is_new_quiet(&self, p: &Pos, mv: &Mv, refutation_index: usize) -> bool {
+        let earlier = [self.killers[0], self.killers[1]];
+        !mv.is_null()
+            && !mv.is_cap()
+            && !mv.is_prom()
+            && !mv.fuzzy_compare(&self.tt_mv)
+            && !earlier[..refutation_index]
+                .iter()
+                .any(|m| m.fuzzy_compare(mv))
+            && mv_gen::is_pseudo_legal(p, mv)
+    }
+
+    fn already_returned(&self, mv: &Mv) -> bool {
+        mv.fuzzy_compare(&self.tt_mv)
+            || mv.fuzzy_compare(&self.killers[0])
+            || mv.fuzzy_compare(&self.killers[1])
+            || mv.fuzzy_compare(&self.counter)
+    }
+}
+
+/// Promotions to a queen first, then captures by victim value & capture history, underpromotions last
+fn noisy_score(p: &Pos, mv: &Mv) -> i32 {
+    let mut score = 0;
+    if mv.is_cap() {
+        score +=
+            8 * see::value(mv.cap_victim()) + history::capture_score(mv, moving_piece(p, mv)) / 8;
+    }
+    if mv.is_prom() {
+        let prom_gain = 8 * (see::value(mv.prom_piece()) - see::value(Piece::Pawn));
+        score += if mv.prom_piece() == Piece::Queen {
+            prom_gain
+        } else {
+            -prom_gain
+        };
+    }
+    score
+}
+
+/// Only valid for pseudo legal moves
+pub fn moving_piece(p: &Pos, mv: &Mv) -> ClrPiece {
+    p.piece_at_sq(mv.sq().0).unwrap()
+}
@@ -0,0 +1,114 @@
+//! # Static Exchange Evaluation
+//! SEE calculates the material outcome of a capture sequence on a single square,
+//! assuming both sides always recapture with their least valuable attacker and may stop at any time.
+//! It is a lot more accurate than MVVLVA (Pawn x Pawn defended by a pawn is equal, Queen x Pawn defended by a pawn is not)
+//! while still being cheap enough to calculate for every capture.
+//! Sliding pieces behind the capturing piece (x-rays) are found by recalculating
+//! the attackers with the updated occupancy after every capture.
+
+use super::constants;
+use super::magic;
+
+use rosa_lib::mv::Mv;
+use rosa_lib::piece::*;
+use rosa_lib::pos::Pos;
+
+const PIECES: [Piece; 6] = [
+    Piece::Pawn,
+    Piece::Knight,
+    Piece::Bishop,
+    Piece::Rook,
+    Piece::Queen,
+    Piece::King,
+];
+
+pub fn value(piece: Piece) -> i32 {
+    match piece {
+        Piece::Pawn => 100,
+        Piece::Knight => 320,
+        Piece::Bishop => 330,
+        Piece::Rook => 500,
+        Piece::Queen => 900,
+        Piece::King => 20000,
+    }
+}
+
+/// Material balance of the exchange started by mv, from the view of the moving side
+pub fn see(p: &Pos, mv: &Mv) -> i32 {
+    let (start, end) = mv.sq();
+    let mut occupied = p.full().val() & !(1u64 << start);
+    let mut gain = [0; 32];
+    let mut depth = 0;
+
+    let mut attacker = match p.piece_at_sq(start) {
+        Some(piece) => piece.de_clr(),
+        None => return 0,
+    };
+
+    if mv.is_cap() {
+        gain[0] = value(mv.cap_victim());
+    }
+    if mv.is_ep() {
+        let captured_sq = if p.clr().is_white() { end - 8 } else { end + 8 };
+        occupied &= !(1u64 << captured_sq);
+    }
+    if mv.is_prom() {
+        attacker = mv.prom_piece();
+        gain[0] += value(attacker) - value(Piece::Pawn);
+    }
+
+    let mut clr = p.clr();
+    loop {
+        depth += 1;
+        clr = clr.flip();
+        // Speculative score if the piece on the square is captured
+        gain[depth] = value(attacker) - gain[depth - 1];
+        if i32::max(-gain[depth - 1], gain[depth]) < 0 || depth == gain.len() - 1 {
+            break;
+        }
+
+        let attackers = attackers_to(p, end, occupied) & occupied;
+        let Some((piece, sq)) = least_valuable(p, attackers, clr) else {
+            break;
+        };
+
+        // The king can only capture if the square is not defended anymore
+        if piece == Piece::King && least_valuable(p, attackers, clr.flip()).is_some() {
+            break;
+        }
+
+        occupied &= !(1u64 << sq);
+        attacker = piece;
+    }
+
+    while depth > 1 {
+        depth -= 1;
+        gain[depth - 1] = -i32::max(-gain[depth - 1], gain[depth]);
+    }
+    gain[0]
+}
+
+/// All pieces (of both colors) that attack sq with the given occupancy
+fn attackers_to(p: &Pos, sq: u8, occupied: u64) -> u64 {
+    let bb =
+        |piece: Piece| p.piece(piece.clr(Clr::White)).val() | p.piece(piece.clr(Clr::Black)).val();
+    let queens = bb(Piece::Queen);
+
+    (magic::bishop_attacks(sq, occupied) & (bb(Piece::Bishop) | queens))
+        | (magic::rook_attacks(sq, occupied) & (bb(Piece::Rook) | queens))
+        | (constants::get_mask(ClrPiece::WKnight, sq) & bb(Piece::Knight))
+        | (constants::get_mask(ClrPiece::WKing, sq) & bb(Piece::King))
+        // A white pawn attacks sq, if a black pawn on sq would attack the white pawn
+        | (constants::get_pawn_mask(Clr::Black, sq, true) & p.piece(ClrPiece::WPawn).val())
+        | (constants::get_pawn_mask(Clr::White, sq, true) & p.piece(ClrPiece::BPawn).val())
+}
+
+fn least_valuable(p: &Pos, attackers: u64, clr: Clr) -> Option<(Piece, u8)> {
+    for piece in PIECES {
+        let bb = attackers & p.piece(piece.clr(clr)).val();
+        if bb != 0 {
+            return Some((piece, bb.trailing_zeros() as u8));
+        }
+    }
+    None
+}
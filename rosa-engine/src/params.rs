@@ -0,0 +1,232 @@
+//! # Search Parameters
+//! The margins, depths & reductions of the search heuristics (see search).
+//! ## Tuning
+//! With the cargo feature `tune` every parameter is a uci spin option,
+//! so an SPSA tuner can play games with different values without recompiling.
+//! Without the feature the parameters are constants & the compiler can fold them.
+//!
+//! The `params` command prints the current values as JSON.
+//! All parameters are integers, the LMR formula is scaled by 100.
+
+#[derive(Clone, Copy, Debug)]
+pub struct SearchParams {
+    // Aspiration windows
+    /// Half the width of the first aspiration window (centipawns)
+    pub aspiration_window: i32,
+    /// Shallow scores are too unstable for aspiration windows
+    pub aspiration_min_depth: i32,
+    /// Use the full window once delta reaches this
+    pub aspiration_max_window: i32,
+
+    // Forward pruning (see Forward Pruning)
+    pub rfp_max_depth: i32,
+    pub rfp_margin: i32,
+    pub razor_max_depth: i32,
+    pub razor_margin: i32,
+    pub futility_max_depth: i32,
+    pub futility_base: i32,
+    pub futility_margin: i32,
+    pub lmp_max_depth: i32,
+    /// Quiet moves after lmp_base + depth^2 moves are skipped
+    pub lmp_base: i32,
+    pub history_prune_max_depth: i32,
+    pub history_prune_margin: i32,
+
+    // Null move pruning (see Null Move Pruning)
+    pub nmp_min_depth: i32,
+    pub nmp_base: i32,
+    /// Another ply of reduction every this many plies of depth
+    pub nmp_depth_divisor: i32,
+    /// Another ply of reduction every this many centipawns the eval is above beta
+    pub nmp_eval_margin: i32,
+    pub nmp_max_eval_reduction: i32,
+    pub nmp_verify_depth: i32,
+
+    // ProbCut (see ProbCut)
+    pub probcut_min_depth: i32,
+    pub probcut_margin: i32,
+    pub probcut_reduction: i32,
+    /// Internal iterative reductions (see Internal Iterative Reductions)
+    /// At least 2, the reduced node still has to search its moves one ply deeper
+    pub iir_min_depth: i32,
+
+    // Extensions (see Extensions)
+    pub se_min_depth: i32,
+    /// The tt entry can be at most this much shallower than the node
+    pub se_tt_depth: i32,
+    /// Centipawns per depth the other moves have to be worse than the tt move
+    pub se_margin: i32,
+
+    // Late move reductions (see Late move reduction)
+    /// How many moves to do before starting late move reductions
+    pub lmr_moves: i32,
+    /// At least 2, a reduced move still needs a ply to search
+    pub lmr_min_depth: i32,
+    /// lmr_base / 100 + ln(depth) * ln(move number) / (lmr_divisor / 100)
+    pub lmr_base: i32,
+    pub lmr_divisor: i32,
+    /// History score per ply of reduction
+    pub lmr_history_divisor: i32,
+}
+
+impl SearchParams {
+    pub const DEFAULT: SearchParams = SearchParams {
+        aspiration_window: 25,
+        aspiration_min_depth: 4,
+        aspiration_max_window: 1000,
+
+        rfp_max_depth: 7,
+        rfp_margin: 80,
+        razor_max_depth: 2,
+        razor_margin: 250,
+        futility_max_depth: 6,
+        futility_base: 100,
+        futility_margin: 90,
+        lmp_max_depth: 6,
+        lmp_base: 3,
+        history_prune_max_depth: 4,
+        history_prune_margin: 2048,
+
+        nmp_min_depth: 3,
+        nmp_base: 3,
+        nmp_depth_divisor: 6,
+        nmp_eval_margin: 200,
+        nmp_max_eval_reduction: 3,
+        nmp_verify_depth: 12,
+
+        probcut_min_depth: 5,
+        probcut_margin: 200,
+        probcut_reduction: 4,
+        iir_min_depth: 4,
+
+        se_min_depth: 8,
+        se_tt_depth: 3,
+        se_margin: 2,
+
+        lmr_moves: 2,
+        lmr_min_depth: 3,
+        lmr_base: 75,
+        lmr_divisor: 225,
+        lmr_history_divisor: 8192,
+    };
+}
+
+/// name, min, max, field
+type Param = (&'static str, i32, i32, fn(&mut SearchParams) -> &mut i32);
+
+/// Every parameter with the range a tuner may use
+const PARAMS: [Param; 32] = [
+    ("aspiration_window", 5, 200, |sp| &mut sp.aspiration_window),
+    ("aspiration_min_depth", 1, 20, |sp| {
+        &mut sp.aspiration_min_depth
+    }),
+    ("aspiration_max_window", 100, 5000, |sp| {
+        &mut sp.aspiration_max_window
+    }),
+    ("rfp_max_depth", 0, 16, |sp| &mut sp.rfp_max_depth),
+    ("rfp_margin", 10, 300, |sp| &mut sp.rfp_margin),
+    ("razor_max_depth", 0, 8, |sp| &mut sp.razor_max_depth),
+    ("razor_margin", 50, 800, |sp| &mut sp.razor_margin),
+    ("futility_max_depth", 0, 16, |sp| &mut sp.futility_max_depth),
+    ("futility_base", 0, 400, |sp| &mut sp.futility_base),
+    ("futility_margin", 10, 300, |sp| &mut sp.futility_margin),
+    ("lmp_max_depth", 0, 16, |sp| &mut sp.lmp_max_depth),
+    ("lmp_base", 0, 20, |sp| &mut sp.lmp_base),
+    ("history_prune_max_depth", 0, 16, |sp| {
+        &mut sp.history_prune_max_depth
+    }),
+    ("history_prune_margin", 0, 16384, |sp| {
+        &mut sp.history_prune_margin
+    }),
+    ("nmp_min_depth", 1, 10, |sp| &mut sp.nmp_min_depth),
+    ("nmp_base", 1, 6, |sp| &mut sp.nmp_base),
+    ("nmp_depth_divisor", 1, 20, |sp| &mut sp.nmp_depth_divisor),
+    ("nmp_eval_margin", 50, 800, |sp| &mut sp.nmp_eval_margin),
+    ("nmp_max_eval_reduction", 0, 6, |sp| {
+        &mut sp.nmp_max_eval_reduction
+    }),
+    ("nmp_verify_depth", 1, 64, |sp| &mut sp.nmp_verify_depth),
+    ("probcut_min_depth", 2, 16, |sp| &mut sp.probcut_min_depth),
+    ("probcut_margin", 50, 800, |sp| &mut sp.probcut_margin),
+    ("probcut_reduction", 1, 8, |sp| &mut sp.probcut_reduction),
+    ("iir_min_depth", 2, 16, |sp| &mut sp.iir_min_depth),
+    ("se_min_depth", 2, 20, |sp| &mut sp.se_min_depth),
+    ("se_tt_depth", 0, 10, |sp| &mut sp.se_tt_depth),
+    ("se_margin", 0, 10, |sp| &mut sp.se_margin),
+    ("lmr_moves", 0, 10, |sp| &mut sp.lmr_moves),
+    ("lmr_min_depth", 2, 10, |sp| &mut sp.lmr_min_depth),
+    ("lmr_base", 0, 300, |sp| &mut sp.lmr_base),
+    ("lmr_divisor", 100, 500, |sp| &mut sp.lmr_divisor),
+    ("lmr_history_divisor", 1024, 32768, |sp| {
+        &mut sp.lmr_history_divisor
+    }),
+];
+
+#[cfg(feature = "tune")]
+static mut SEARCH_PARAMS: SearchParams = SearchParams::DEFAULT;
+
+#[cfg(feature = "tune")]
+#[inline(always)]
+pub fn params() -> SearchParams {
+    // Only written while no search is running
+    unsafe { SEARCH_PARAMS }
+}
+
+#[cfg(not(feature = "tune"))]
+#[inline(always)]
+pub fn params() -> SearchParams {
+    SearchParams::DEFAULT
+}
+
+/// The name is case insensitive, the value is clamped to the range of the parameter
+/// Returns false if there is no such parameter
+/// # Safety
+/// No search can be running
+#[cfg(feature = "tune")]
+pub unsafe fn set_param(name: &str, value: i32) -> bool {
+    let Some((_, min, max, field)) = PARAMS
+        .iter()
+        .find(|(param, ..)| param.eq_ignore_ascii_case(name))
+    else {
+        return false;
+    };
+    let mut sp = params();
+    *field(&mut sp) = value.clamp(*min, *max);
+    unsafe { SEARCH_PARAMS = sp };
+    true
+}
+
+/// Not tunable without the feature
+/// # Safety
+/// Always safe, only the tune version writes the parameters
+#[cfg(not(feature = "tune"))]
+pub unsafe fn set_param(_name: &str, _value: i32) -> bool {
+    false
+}
+
+/// name, min & max of every parameter
+pub fn ranges() -> impl Iterator<Item = (&'static str, i32, i32)> {
+    PARAMS.iter().map(|(name, min, max, _)| (*name, *min, *max))
+}
+
+/// The uci options of the parameters (only with the feature)
+pub fn print_options() {
+    if !cfg!(feature = "tune") {
+        return;
+    }
+    let mut sp = SearchParams::DEFAULT;
+    for (name, min, max, field) in PARAMS {
+        let default = *field(&mut sp);
+        println!("option name {name} type spin default {default} min {min} max {max}");
+    }
+}
+
+/// A single line, e.g. {"aspiration_window": 25, ...}
+pub fn to_json() -> String {
+    let mut sp = params();
+    let values: Vec<String> = PARAMS
+        .iter()
+        .map(|(name, _, _, field)| format!("\"{name}\": {}", field(&mut sp)))
+        .collect();
+    format!("{{{}}}", values.join(", "))
+}
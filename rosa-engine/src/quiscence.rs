@@ -13,16 +13,68 @@
 //! We use the current static eval as a "stand pat" - a lower bound.
 //! If a capture we check is worse than the stand pat value
 //! it obviously isnt a good move. (Once again based on the null move hypothesis)
+//! ## Check Evasions
+//! If we are in check, standing pat is not an option. Instead all moves are searched,
+//! so mates at the horizon are found.
+//! ## Quiet Checks
+//! At the first ply of the quiscence search quiet moves that give check are searched as well.
+//! ## Pruning
+//! - Captures that lose material (SEE < 0) are skipped
+//! - Delta pruning: If even winning the captured piece (plus a margin) cant raise alpha, the capture is skipped
+//! ## Transposition Table
+//! Results are saved at depth 0, so every entry of the normal search is deep enough for a cutoff.
 //!
 
+use rosa_lib::mv::Mv;
 use rosa_lib::pos::Pos;
+use rosa_lib::score::Score;
+use rosa_lib::tt;
 
-use crate::{eval, make, mv::mv_gen, mv::mv_gen::MvGenStage};
+use crate::search::{self, TT, TtRes};
+use crate::stack;
+use crate::thread_search::SearchStats;
+use crate::{eval, make, mv::picker::MovePicker, mv::see};
 
-pub fn quiscence_search(pos: &mut Pos, mut alpha: i32, beta: i32) -> i32 {
-    let stand_pat = eval::eval(pos);
+/// Search quiet checks at the first ply
+const QS_CHECKS: bool = true;
+/// Added to the value of the captured piece
+const DELTA_MARGIN: i32 = 200;
 
-    let mut best = stand_pat;
+/// ply: Distance from the root, for the seldepth & mate scores
+/// checks: First ply of the quiscence search (see Quiet Checks)
+pub fn quiscence_search(
+    pos: &mut Pos, mut alpha: Score, beta: Score, ply: usize, checks: bool, stats: &mut SearchStats,
+) -> Score {
+    stats.node(ply);
+    if ply >= stack::MAX_PLY {
+        return Score::cp(eval::eval(pos));
+    }
+
+    // Only the cutoff (outside of pv nodes) & the move are used:
+    // A window narrowed by the tt would leave the result & its bound type relative to the wrong window
+    let pv_node = beta.val() - alpha.val() > 1;
+    let (mut tt_alpha, mut tt_beta) = (alpha, beta);
+    let tt_mv = match search::parse_tt(pos, 0, ply, &mut tt_alpha, &mut tt_beta) {
+        TtRes::Miss => None,
+        TtRes::Cutoff(score, _) if !pv_node => {
+            stats.tt_hit();
+            return score;
+        }
+        TtRes::Cutoff(_, mv) | TtRes::MvHint(mv) => {
+            stats.tt_hit();
+            mv
+        }
+    };
+
+    let in_check = make::king_in_check(pos);
+    let old_alpha = alpha;
+    let stand_pat = Score::cp(eval::eval(pos));
+    let mut best = if in_check {
+        // Only moves can save us
+        Score::mated_in(ply)
+    } else {
+        stand_pat
+    };
     // Even if we dont do anything we still fall out of the window
     if best >= beta {
         return best;
@@ -32,37 +84,68 @@ pub fn quiscence_search(pos: &mut Pos, mut alpha: i32, beta: i32) -> i32 {
     if best > alpha {
         alpha = best;
     }
+    let mut best_mv = Mv::null();
 
-    // We dont do quite moves during quiscence (except non capture promotions)
-    let mut iter = mv_gen::gen_mvs_stages(pos, MvGenStage::Cap);
-    iter.append(&mut mv_gen::gen_mvs_stages(pos, MvGenStage::Prom));
+    let checks = checks && QS_CHECKS && !in_check;
+    // We dont do quite moves during quiscence (except non capture promotions & checks)
+    let mut picker = if in_check {
+        MovePicker::new(tt_mv, [Mv::null(); 2], Mv::null(), [None; 2])
+    } else {
+        MovePicker::new_noisy(tt_mv, checks)
+    };
 
-    for mut mv in iter {
-        let (legal, guard) = make::make(pos, &mut mv);
-        let score;
-        match legal {
-            make::Legal::ILLEGAL => {
-                make::unmake(pos, mv, guard);
+    while let Some(mut mv) = picker.next(pos) {
+        let is_quiet = !mv.is_cap() && !mv.is_prom();
+        if !in_check && !is_quiet {
+            // Delta pruning (promotions could gain a lot more)
+            if !mv.is_prom() && stand_pat + (see::value(mv.cap_victim()) + DELTA_MARGIN) <= alpha {
                 continue;
             }
-            make::Legal::LEGAL => {
-                score = -quiscence_search(pos, -beta, -alpha);
-                make::unmake(pos, mv, guard);
+            // The picker only checks the SEE of the captures it generated
+            if tt_mv.is_some_and(|tt_mv| tt_mv.fuzzy_compare(&mv)) && see::see(pos, &mv) < 0 {
+                continue;
             }
         }
 
-        // This move beats the window
-        if score >= beta {
-            return score;
+        let (legal, guard) = make::make(pos, &mut mv);
+        // Quiet moves are only searched if they evade or give check
+        if legal == make::Legal::ILLEGAL
+            || (!in_check && is_quiet && !(checks && make::king_in_check(pos)))
+        {
+            make::unmake(pos, mv, guard);
+            continue;
         }
+        let score = -quiscence_search(pos, -beta, -alpha, ply + 1, false, stats);
+        make::unmake(pos, mv, guard);
+
         if score > best {
             best = score;
         }
         if score > alpha {
             alpha = score;
+            best_mv = mv;
+        }
+        // This move beats the window
+        if score >= beta {
+            break;
         }
     }
 
-    // If there are no legal captures we return standpat
-    return best;
+    let node_type = if best >= beta {
+        tt::EntryType::Lower
+    } else if best > old_alpha {
+        tt::EntryType::Exact
+    } else {
+        tt::EntryType::Upper
+    };
+    TT.set(tt::Entry::new(
+        pos.key(),
+        best.to_tt(ply),
+        best_mv,
+        0,
+        node_type,
+    ));
+
+    // If there are no legal captures we return standpat (or mate if we are in check)
+    best
 }
@@ -1,17 +1,22 @@
 //! # UCI Handling
 //! Spawns a separate thread to handle both stdin and timeouts
+//! The commands themselves are parsed in uci
 
 use crate::config;
+use crate::cuckoo;
 use crate::eval;
 use crate::eval::eval;
 use crate::fen;
 use crate::make;
-use crate::make::MakeGuard;
 use crate::mv;
+use crate::params;
 use crate::search;
 use crate::thread_search;
 use crate::time;
+use crate::time::SearchLimits;
 use crate::time::StartSearch;
+use crate::time::TimeManager;
+use crate::uci::{TtAction, UciCommand};
 
 use crossbeam::channel;
 use crossbeam::select;
@@ -20,10 +25,11 @@ use rosa_lib::mv::Mv;
 use rosa_lib::pos::*;
 use rosa_lib::tt;
 
-use core::panic;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
 use std::sync::Once;
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 enum State {
     UnInit,
@@ -34,14 +40,13 @@ enum State {
         channel::Receiver<Option<Mv>>,
         thread_search::Stop,
     ),
-    Pause(Pos, Mv),
-    NoLegalMovesPause(Pos),
 }
 
+/// The clock of the search is in its Stop (see time)
 pub enum SearchState {
-    Timed(Instant, Duration),
-    Untimed,
-    Ponder(Duration, Mv, MakeGuard),
+    Normal,
+    /// The position already contains the ponder move, the clock starts at ponderhit
+    Ponder,
 }
 static INIT: Once = Once::new();
 
@@ -51,7 +56,10 @@ pub fn init() {
         rosa_lib::lib_init();
         tt::init_zobrist_keys();
         mv::magic_init::init_magics();
-        search::TT.resize(config::TT_SIZE);
+        cuckoo::init_cuckoo();
+        // No search is running yet
+        unsafe { search::TT.resize(config::tt_size(config::DEFAULT_TABLE_SIZE_MB)) };
+        search::init_lmr();
         eval::init_eval();
     });
 }
@@ -70,98 +78,151 @@ impl State {
     }
 
     #[must_use]
-    fn start_search(mut self, state: time::StartSearch) -> Self {
+    fn start_search(mut self, state: time::StartSearch, limits: SearchLimits) -> Self {
         match self {
             State::UnInit => {
                 self = self.init();
-                self.start_search(state)
+                self.start_search(state, limits)
             }
             State::Init(p) => {
-                let search_state = match state {
-                    StartSearch::Ponder(_dur) => {
-                        panic!("Pondering without having searched first")
+                let (search_state, time) = match state {
+                    StartSearch::Ponder(time) => (SearchState::Ponder, time),
+                    StartSearch::Timed(time) => {
+                        time.start();
+                        (SearchState::Normal, time)
                     }
-                    StartSearch::Timed(dur) => SearchState::Timed(Instant::now(), dur),
-                    StartSearch::Untimed => SearchState::Untimed,
-                };
-                let (rec, stop) = thread_search::start_thread_search(&p);
-                State::Search(p, search_state, rec, stop)
-            }
-            State::Pause(mut p, mut ponder) => {
-                let (rec, stop) = thread_search::start_thread_search(&p);
-                let search_state = match state {
-                    StartSearch::Ponder(dur) => {
-                        // If search is paused we wait for a ponder move
-                        // -> Save ponder move in
-                        let (_legal, guard) = make::make(&mut p, &mut ponder);
-                        SearchState::Ponder(dur, ponder, guard)
-                    }
-                    StartSearch::Timed(dur) => SearchState::Timed(Instant::now(), dur),
-                    StartSearch::Untimed => SearchState::Untimed,
+                    StartSearch::Untimed => (SearchState::Normal, TimeManager::infinite()),
                 };
+                let (rec, stop) = thread_search::start_thread_search(&p, limits, time);
                 State::Search(p, search_state, rec, stop)
             }
             State::Search(..) => {
                 self = self.pause_search();
-                self.start_search(state)
-            }
-            State::NoLegalMovesPause(..) => {
-                panic!("No legal moves in this position")
+                self.start_search(state, limits)
             }
         }
     }
 
+    /// Stops the search & waits for the bestmove
     #[must_use]
     fn pause_search(self) -> Self {
         match self {
-            State::Search(mut p, search_state, rec, mut stop) => {
+            State::Search(_, _, ref rec, ref stop) => {
+                stop.release();
                 stop.stop_search();
-                if let SearchState::Ponder(_, mv, guard) = search_state {
-                    make::unmake(&mut p, mv, guard);
-                }
-                let ponder = rec.recv().unwrap();
-                match ponder {
-                    Some(pon) => {
-                        return State::Pause(p, pon);
-                    }
-                    None => {
-                        return State::NoLegalMovesPause(p);
-                    }
-                }
+                // A disconnected channel means the search is gone as well
+                let _ = rec.recv();
+                self.search_done()
             }
             _ => self,
         }
     }
 
+    /// The search threads have sent the bestmove (stopped or a search limit was reached)
+    #[must_use]
+    fn search_done(self) -> Self {
+        match self {
+            State::Search(p, _, _, _) => State::Init(p),
+            _ => self,
+        }
+    }
+
+    /// Never recieves anything while no search is running
+    fn search_rec(&self) -> channel::Receiver<Option<Mv>> {
+        match self {
+            State::Search(_, _, rec, _) => rec.clone(),
+            _ => channel::never(),
+        }
+    }
+
+    /// The ponder search keeps running, only its clock is started
     #[must_use]
     fn ponder_hit(self) -> Self {
-        if let State::Search(mut p, SearchState::Ponder(dur, mut mv, guard), rec, stop) = self {
-            make::unmake(&mut p, mv, guard);
-            make::unchecked_make(&mut p, &mut mv);
-            return State::Search(p, SearchState::Timed(Instant::now(), dur), rec, stop);
+        match self {
+            State::Search(p, SearchState::Ponder, rec, stop) => {
+                stop.ponder_hit();
+                State::Search(p, SearchState::Normal, rec, stop)
+            }
+            _ => {
+                println!("info string ponderhit ignored, not pondering");
+                self
+            }
         }
-        panic!("Ponder hit while not pondering")
     }
 
-    fn get_timeout(&self) -> Duration {
-        if let State::Search(_, SearchState::Timed(start, dur), _, _) = self {
-            let elapsed = start.elapsed();
-            if elapsed >= *dur {
-                return Duration::from_millis(0);
-            } else {
-                return *dur - elapsed;
+    fn is_searching(&self) -> bool {
+        matches!(self, State::Search(..))
+    }
+
+    /// The TT can only be changed while no search thread is accessing it
+    #[must_use]
+    fn set_option(mut self, name: &str, value: Option<String>) -> Self {
+        match name.to_lowercase().as_str() {
+            "threads" => {
+                if let Some(count) = value.and_then(|v| v.parse().ok()) {
+                    thread_search::set_thread_count(count);
+                }
+            }
+            "move overhead" => {
+                if let Some(millis) = value.and_then(|v| v.parse().ok()) {
+                    time::set_move_overhead(millis);
+                }
+            }
+            "contempt" => {
+                if let Some(cp) = value.and_then(|v| v.parse().ok()) {
+                    search::set_contempt(cp);
+                }
+            }
+            "multipv" => {
+                if let Some(lines) = value.and_then(|v| v.parse().ok()) {
+                    thread_search::set_multipv(lines);
+                }
+            }
+            "hash" | "clear hash" => {
+                // Initializing later would overwrite the table size
+                self = self.init();
+                if self.is_searching() {
+                    println!("info string {name} ignored during a search");
+                    return self;
+                }
+                if name.eq_ignore_ascii_case("clear hash") {
+                    search::TT.clear();
+                } else if let Some(mb) = value.and_then(|v| v.parse().ok()) {
+                    // No search is running
+                    unsafe { search::TT.resize(config::tt_size(mb)) };
+                }
+            }
+            _ => {
+                let Some(value) = value.and_then(|v| v.parse().ok()) else {
+                    return self;
+                };
+                if self.is_searching() {
+                    println!("info string {name} ignored during a search");
+                    return self;
+                }
+                // No search is running
+                if unsafe { params::set_param(name, value) } {
+                    search::init_lmr();
+                }
             }
         }
-        Duration::from_millis(u64::MAX)
+        self
     }
 
+    /// The search polls the hard bound itself, this is the backup
+    fn get_timeout(&self) -> Duration {
+        let never = Duration::from_millis(u64::MAX);
+        match self {
+            State::Search(_, _, _, stop) => stop.time().remaining().min(never),
+            _ => never,
+        }
+    }
+
+    /// Every command but uci, setoption & quit initializes the state first
     fn get_pos(&self) -> &Pos {
         match self {
-            State::Init(p)
-            | State::Pause(p, _)
-            | State::NoLegalMovesPause(p)
-            | State::Search(p, _, _, _) => p,
-            State::UnInit => panic!("Command used before initialized (isready)"),
+            State::Init(p) | State::Search(p, _, _, _) => p,
+            State::UnInit => unreachable!("Commands initialize the state first"),
         }
     }
 
@@ -169,8 +230,6 @@ impl State {
     fn set_pos(mut self, new_pos: Pos) -> Self {
         self = match self {
             State::Init(_) => State::Init(new_pos),
-            State::Pause(_, ponder) => State::Pause(new_pos, ponder),
-            State::NoLegalMovesPause(_) => State::NoLegalMovesPause(new_pos),
             State::Search(_, state, rec, stop) => State::Search(new_pos, state, rec, stop),
             State::UnInit => {
                 self = self.init();
@@ -186,7 +245,10 @@ pub fn start() {
     thread::spawn(move || {
         loop {
             let mut buf = String::new();
-            std::io::stdin().read_line(&mut buf).unwrap();
+            // The gui closed stdin (or it broke), nobody can talk to us anymore
+            if !matches!(std::io::stdin().read_line(&mut buf), Ok(1..)) {
+                buf = "quit".to_string();
+            }
             tx.send(buf).unwrap();
         }
     });
@@ -195,128 +257,170 @@ pub fn start() {
 
     loop {
         let timeout = state.get_timeout();
+        let search_rec = state.search_rec();
         let cmd: String;
         select! {
             recv(rx) -> c => {
                 cmd = c.unwrap();
             }
+            recv(search_rec) -> _ => {
+                state = state.search_done();
+                continue;
+            }
             default(timeout) => {
                 state = state.pause_search();
                 continue;
             }
         }
 
-        let cmd_parts: Vec<&str> = cmd.split_ascii_whitespace().collect();
-        if cmd_parts.is_empty() {
+        if cmd.trim().is_empty() {
             continue;
         }
+        let cmd = match UciCommand::parse(&cmd) {
+            Ok(cmd) => cmd,
+            Err(e) => {
+                println!("info string {e}");
+                continue;
+            }
+        };
+
+        // Commands can arrive in any order, e.g. go before isready
+        if !matches!(
+            cmd,
+            UciCommand::Uci | UciCommand::SetOption { .. } | UciCommand::Quit
+        ) {
+            state = state.init();
+        }
 
-        match cmd_parts[0].to_lowercase().as_str() {
-            "uci" => {
+        match cmd {
+            UciCommand::Uci => {
                 println!("id name {} {}", config::NAME, config::VERSION);
                 println!("id author {}", config::AUTHOR);
                 print_options();
                 println!("uciok");
             }
 
-            "isready" => {
-                state = state.init();
+            UciCommand::Debug(on) => thread_search::set_debug(on),
+
+            UciCommand::IsReady => {
                 println!("readyok");
             }
 
-            "position" => {
-                if cmd_parts.len() == 1 {
-                    continue;
-                }
+            UciCommand::Register => {}
 
-                let split = cmd.split_once(" moves ");
-                let fen: Vec<&str>;
-                let mut moves = Vec::new();
-                match split {
-                    Some((f, m)) => {
-                        fen = f.split_ascii_whitespace().collect();
-                        moves = m.split_ascii_whitespace().collect();
-                    }
-                    None => {
-                        fen = cmd_parts[2..].to_vec();
-                    }
-                }
-
-                match cmd_parts[1] {
-                    "startpos" => state = state.set_pos(fen::starting_pos(moves)),
-                    "fen" => state = state.set_pos(fen::fen(fen, moves)),
-                    _ => continue,
+            UciCommand::Position { fen, moves } => {
+                let moves = moves.iter().map(String::as_str).collect();
+                let pos = match fen {
+                    Some(fen) => fen::try_fen(fen.iter().map(String::as_str).collect(), moves),
+                    None => fen::try_fen(fen::START_FEN.to_vec(), moves),
+                };
+                match pos {
+                    Ok(pos) => state = state.set_pos(pos),
+                    Err(e) => println!("info string position ignored: {e}"),
                 }
             }
 
-            "quit" => std::process::exit(0),
+            UciCommand::Quit => std::process::exit(0),
 
-            "stop" => {
+            UciCommand::Stop => {
                 state = state.pause_search();
             }
 
-            "go" => {
-                let go_res = time::parse_time_from_go(cmd_parts, state.get_pos().clr());
-                state = state.start_search(go_res);
+            UciCommand::Go(go) => {
+                let (go_res, limits) = go.start(state.get_pos().clr());
+                state = state.start_search(go_res, limits);
             }
 
-            "moves" => {
-                println!("Warning: Does not check legality");
-                if cmd_parts.len() < 2 {
-                    continue;
-                }
-
-                let mv = cmd_parts[1];
+            UciCommand::Move(mv_str) => {
                 let mut pos = state.get_pos().clone();
-                let mut mv = Mv::new_from_str(mv, &pos);
+                let Some(mut mv) = fen::find_mv(&mut pos, &mv_str) else {
+                    println!("info string illegal move {mv_str}");
+                    continue;
+                };
                 println!("{:?}", mv);
 
-                make::make(&mut pos, &mut mv);
+                make::unchecked_make(&mut pos, &mut mv);
 
                 state = state.set_pos(pos);
             }
 
             // Start division search at current node
             // Only for debugging
-            "div" => {
+            UciCommand::Div(depth) => {
                 let mut pos = state.get_pos().clone();
-                let depth = cmd_parts[1].parse().unwrap();
                 search::debug_division_search(&mut pos, depth);
             }
 
-            "print" | "p" | "d" => {
+            UciCommand::Print => {
                 println!("{}", state.get_pos());
             }
 
-            "key" => {
+            UciCommand::Key => {
                 println!("Key: {:?}", state.get_pos().key());
             }
 
-            "magics" => {
+            UciCommand::Params => {
+                println!("{}", params::to_json());
+            }
+
+            UciCommand::Magics => {
                 mv::gen_magics::gen_magics();
             }
 
-            "eval" => {
-                let eval = eval(&state.get_pos());
+            UciCommand::Eval => {
+                let eval = eval(state.get_pos());
                 println!("Eval: {eval}");
             }
 
-            "color" => {
+            UciCommand::Color => {
                 let mut pos_clone = state.get_pos().clone();
                 pos_clone.flip_color();
                 state = state.set_pos(pos_clone);
             }
 
-            "ponderhit" => {
+            UciCommand::PonderHit => {
                 state = state.ponder_hit();
             }
 
-            "setoption" => {
-                //println!("Options currently not supported");
+            UciCommand::SetOption { name, value } => {
+                state = state.set_option(&name, value);
+            }
+
+            UciCommand::Tt(action, path) => {
+                if state.is_searching() {
+                    println!("info string tt ignored during a search");
+                    continue;
+                }
+                match tt_file(&action, &path) {
+                    Ok(()) => println!("info string tt {path} done"),
+                    Err(e) => println!("info string tt {path} failed: {e}"),
+                }
             }
 
-            "ucinewgame" => {}
-            _ => {}
+            // The history, killer & counter tables are thread local to the search threads,
+            // which are spawned for every search -> Only the TT survives between games
+            UciCommand::UciNewGame => {
+                if state.is_searching() {
+                    println!("info string ucinewgame ignored during a search");
+                    continue;
+                }
+                search::TT.clear();
+            }
+        }
+    }
+}
+
+/// Only call this while no search is running
+fn tt_file(action: &TtAction, path: &str) -> std::io::Result<()> {
+    match action {
+        TtAction::Save => {
+            let mut file = BufWriter::new(File::create(path)?);
+            search::TT.save(&mut file)
+        }
+        TtAction::Load => {
+            let mut file = BufReader::new(File::open(path)?);
+            // No search is running
+            unsafe { search::TT.load(&mut file) }
         }
     }
 }
@@ -328,6 +432,32 @@ fn print_options() {
         config::MIN_TABLE_SIZE_MB,
         config::MAX_TABLE_SIZE_MB
     );
+    println!(
+        "option name Threads type spin default {} min {} max {}",
+        config::DEFAULT_THREADS,
+        config::MIN_THREADS,
+        config::MAX_THREADS
+    );
+    println!(
+        "option name MultiPV type spin default {} min {} max {}",
+        config::DEFAULT_MULTIPV,
+        config::MIN_MULTIPV,
+        config::MAX_MULTIPV
+    );
+    println!(
+        "option name Move Overhead type spin default {} min {} max {}",
+        config::DEFAULT_MOVE_OVERHEAD,
+        config::MIN_MOVE_OVERHEAD,
+        config::MAX_MOVE_OVERHEAD
+    );
+    println!(
+        "option name Contempt type spin default {} min {} max {}",
+        config::DEFAULT_CONTEMPT,
+        config::MIN_CONTEMPT,
+        config::MAX_CONTEMPT
+    );
+    println!("option name Clear Hash type button");
+    params::print_options();
     if config::PONDER {
         println!("option name Ponder type check default true");
     }
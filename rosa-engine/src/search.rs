@@ -59,191 +59,483 @@
 //! beta pruning.
 //! ### Null Move Pruning
 //! Null move pruning works under the assumption that doing nothing is always worse than doing something.
-//! The assumption holds in practise except for very specific scenarios (zugzwang), which occur so few times,
-//! that they are not worth considering checking for.  
+//! The assumption holds in practise except for very specific scenarios (zugzwang).
 //! Null move pruning therefor searches using a null move (= doing nothing) before even calculating possible
 //! moves in a position.  
-//! This allows us to warm up our transposition table and establish a lower bound for what a move in a position
-//! should be able to do. This translates into increasing our beta value, which has an effect of the
-//! whole subtree.
+//! If even passing the turn fails high in a reduced search, the node most likely fails high as well.
+//! The reduction grows with the depth & with how far the static eval is above beta.
+//! Zugzwang mostly happens in endgames, so there is no null move if the side to move only has pawns left.
+//! At high depths a fail high is verified by a normal search (without null moves) at the reduced depth.
+//! The null search can not prove a mate, as such mate scores are never returned.
 //! ### Late move reduction
 //! If we have good move it stands to reason that we dont have to check later moves as thoroughly as better scored moves.
 //! As described above this also allows to "underbid" the depth of previous searches and massivly gain from
 //! transposition table entries.  
 //! It is important to remeber than this reductions happens at every depth, as such moves that statically evaluate as bad
 //! get searched quite shallowly.   
-//! There are a lot of formulas ans heuristic used to decide to what exactly we can reduce our depth.
-//! Rosa Chess uses a simple formula of: if depth < 6 {depth - 1} else {depth/3}
-//! This formula is definitely open to changes with further testing
+//! The base reduction grows with log(depth) * log(move number) & is precomputed in a table.
+//! It is reduced less in PV nodes, when the eval is improving, for moves with a good history,
+//! killer & counter moves & moves that give check (& more for moves with a bad history).
+//! A reduced move that beats alpha is re-searched at full depth.
+//! ### Killer & Counter Moves
+//! Quiet moves that caused a beta cutoff are remembered twice: per ply (killer moves, see stack)
+//! and as a reply to the previous move (counter moves). Both are ordered after captures,
+//! but before the rest of the quiet moves, which are ordered by the history heuristic.
+//! ### Forward Pruning
+//! Close to the leaves the static eval is a good enough guess to skip parts of the tree.
+//! None of these are done at PV nodes or while in check, all margins are in centipawns per depth.
+//! - Reverse futility pruning: If the static eval beats beta by a margin, the node is a likely cut node
+//! - Razoring: If the static eval is far below alpha, only the quiscence search can save the node
+//! - Futility pruning: Quiet moves cant raise a static eval that is far below alpha
+//! - Late move pruning: After enough moves the remaining quiet moves are skipped
+//! - History pruning: Quiet moves with a very bad history are skipped
+//!
+//! Quiet moves that give check are never pruned.
+//! ### ProbCut
+//! If a good capture beats beta by a large margin in a reduced search,
+//! the full depth search would most likely beat beta as well.
+//! Only captures that do not lose material (SEE) are tried.
+//! Every capture is first checked by the quiscence search, the reduced search only follows if that succeeds.
+//! ### Internal Iterative Reductions
+//! A PV node without a TT move has probably never been searched (or the TT entry was overwritten).
+//! Its move ordering is bad, so we search it one ply shallower and let the next iteration fill in the TT move.
+//! ### Extensions
+//! Forcing lines should not be cut off by the horizon, so some moves are searched one ply deeper:
+//! - Check extension: Moves that give check
+//! - Singular extension: If the TT move beats all other moves by a margin (verified by a reduced search
+//!   that excludes the TT move), it is the only good move & gets extended
+//! - Recapture extension (optional): Capturing back on the square of the last capture
+//! - Pawn push extension (optional): Pawn pushes to the seventh rank
+//!
+//! Every move is extended at most once & the extensions on a path are limited to the root depth,
+//! so the search cant explode.
+//! ### Contempt
+//! A draw is not worth 0 for both sides: The `Contempt` option is subtracted from the draw score of the root side
+//! (& added for the opponent), so a positive contempt avoids repetitions & stalemates.
+//! The contempt grows if the root position is already better (& shrinks if it is worse)
+//! and is halved towards the endgame, where draws are more likely to be the fair result.
 //! ## Node Types
 
+use crate::config;
+use crate::cuckoo;
 use crate::eval;
 use crate::make;
 use crate::make::Legal;
 use crate::mv::mv_gen;
+use crate::mv::mv_gen::MvList;
+use crate::mv::picker;
+use crate::mv::picker::MovePicker;
+use crate::mv::see;
+use crate::params::params;
 use crate::quiscence::quiscence_search;
+use crate::stack;
+use crate::stack::Stack;
 use crate::thread_search::*;
+use crate::time::SearchLimits;
 
+use rosa_lib::counter;
 use rosa_lib::history;
 use rosa_lib::mv::Mv;
 use rosa_lib::piece::*;
 use rosa_lib::pos;
+use rosa_lib::score::Score;
 use rosa_lib::tt;
 
+use std::sync::atomic::{AtomicI32, Ordering};
 use std::sync::mpsc;
+use std::time::Instant;
 
 pub static TT: tt::TT = tt::TT::new();
+static CONTEMPT: AtomicI32 = AtomicI32::new(config::DEFAULT_CONTEMPT);
+
+pub fn set_contempt(cp: i32) {
+    let cp = cp.clamp(config::MIN_CONTEMPT, config::MAX_CONTEMPT);
+    CONTEMPT.store(cp, Ordering::Relaxed);
+}
+
+// Extensions (see Extensions)
+const RECAPTURE_EXTENSION: bool = true;
+const PAWN_PUSH_EXTENSION: bool = true;
+
+// Contempt (see Contempt)
+/// The most contempt can grow (or shrink) because of the root eval
+const CONTEMPT_EVAL_SCALE: i32 = 50;
+/// At this eval the contempt grows by half of the scale
+const CONTEMPT_EVAL_HALF: i32 = 200;
 
 /// Iterative deepening
-pub fn search(mut p: pos::Pos, sender: mpsc::Sender<ThreadReport>, stop: Stop) {
-    let mut depth = 0;
+/// Helper threads (thread_id > 0) start at different depths, so the threads dont all search the same tree.
+/// All threads share the TT, the history & killer tables are per thread
+/// With multipv > 1 every depth searches the root multiple times, every time excluding the best moves of the previous lines
+/// The main thread stops all threads once one of the limits is reached,
+/// the node limit only counts the nodes of the main thread & never interrupts depth 1
+/// The main thread also decides if there is enough time for another iteration (see time)
+pub fn search(
+    mut p: pos::Pos, sender: mpsc::Sender<ThreadReport>, stop: Stop, thread_id: usize,
+    multipv: usize, limits: SearchLimits,
+) -> Option<()> {
+    let mut depth = (thread_id % 2) as u8;
+    let mut stack = Stack::new();
+    // Counts the nodes of the whole search
+    let mut stats = SearchStats::new(depth);
+    if thread_id == MAIN_THREAD
+        && let Some(max) = limits.nodes
+    {
+        stats.set_node_limit(max);
+    }
+    // Time management
+    let mut prev_best: Option<(Mv, Score)> = None;
+    let mut stability = 0;
+
+    let mut root_mvs = legal_mvs(&mut p);
+    // Checkmate or stalemate, there is nothing to search (see Fallback Bestmove)
+    if root_mvs.is_empty() {
+        return None;
+    }
+    let searchmoves: Vec<Mv> = root_mvs
+        .iter()
+        .copied()
+        .filter(|mv| limits.searchmoves.contains(&mv.to_string()))
+        .collect();
+    // Illegal searchmoves are ignored
+    if !searchmoves.is_empty() {
+        root_mvs = searchmoves;
+        stack.restrict_root(root_mvs.clone());
+    }
+    stack.set_contempt(root_contempt(&p));
+    let multipv = multipv.clamp(1, root_mvs.len().max(1));
+    // The score of every line in the last iteration, for the aspiration windows
+    let mut prev_scores = vec![None; multipv];
 
     loop {
         depth += 1;
+        if depth > 1 && limits.depth_reached(depth - 1) {
+            if thread_id == MAIN_THREAD {
+                stop.stop_search();
+            }
+            return Some(());
+        }
+        stack.clear_root_excluded();
+        let mut main_score = Score::DRAW;
+        let mut main_mv = Mv::null();
+        let iteration_start = Instant::now();
 
-        let score;
-        let mut best_mv;
-        let mut ponder = None;
-        let mut search_stats = SearchStats::new(depth);
+        for line in 1..=multipv {
+            stats.new_iteration(depth);
+            let send = |score, bound, pv, stats: &SearchStats| {
+                let report =
+                    ThreadReport::new(thread_id, line, depth, score, bound, pv, stats.clone());
+                sender.send(report).unwrap();
+            };
 
-        match negascout(
-            &mut p,
-            depth,
-            eval::SAFE_MIN_SCORE,
-            eval::SAFE_MAX_SCORE,
-            &mut search_stats,
-            &stop,
-        ) {
-            SearchRes::TimeOut => {
-                return;
-            }
-            SearchRes::Node(mv, pon, s) => {
-                score = s;
-                best_mv = mv;
-                ponder = Some(pon);
-            }
-            SearchRes::Leaf(s) => {
-                panic!("Root is a leaf Node. Score: {}", s)
-            }
-            SearchRes::NoPonderNode(mv, s) => {
-                score = s;
-                best_mv = mv;
+            let res = aspiration(
+                &mut p,
+                &mut stack,
+                &mut stats,
+                &stop,
+                prev_scores[line - 1],
+                send,
+            );
+            let Some((score, mv)) = res else {
+                // Out of nodes
+                if thread_id == MAIN_THREAD {
+                    stop.stop_search();
+                }
+                return None;
+            };
+            prev_scores[line - 1] = Some(score);
+            stack.exclude_root_mv(mv);
+            if line == 1 {
+                main_score = score;
+                main_mv = mv;
             }
         }
 
-        // Only sende no ponder move is there really is no legal move
-        if ponder.is_none() {
-            let mut p = p.clone();
-            make::unchecked_make(&mut p, &mut best_mv);
-            // Ponder is in TT
-            if let Some(entry) = TT.get(p.key())
-                && entry.key == p.key()
+        if thread_id == MAIN_THREAD {
+            let mut score_drop = 0;
+            if let Some((prev_mv, prev_score)) = prev_best {
+                stability = if prev_mv.fuzzy_compare(&main_mv) {
+                    stability + 1
+                } else {
+                    0
+                };
+                if !prev_score.is_mate() && !main_score.is_mate() {
+                    score_drop = prev_score.val() - main_score.val();
+                }
+            }
+            prev_best = Some((main_mv, main_score));
+            if stop
+                .time()
+                .stop_iterating(stability, score_drop, iteration_start.elapsed())
             {
-                ponder = Some(entry.mv);
-            } else {
-                // If there are no legal moves -> ponder = none
-                // Will return since we are mate / stalemate in 1
-                ponder = mv_gen::gen_mvs(&p).pop();
+                stop.stop_search();
+                return Some(());
             }
         }
 
-        sender
-            .send(ThreadReport::new(
-                depth,
-                score,
-                best_mv,
-                ponder,
-                search_stats.clone(),
-            ))
-            .unwrap();
+        if thread_id == MAIN_THREAD && limits.mate_found(main_score.mate_moves()) {
+            stop.stop_search();
+            return Some(());
+        }
 
-        if score == eval::SAFE_MIN_SCORE || score == eval::SAFE_MAX_SCORE || score == 0 {
+        if main_score.is_mate() || main_score == Score::DRAW {
             // If the TT entry for the current position is at the current depth
             // -> So we dont spin infinitly on a small tree
             if let Some(entry) = TT.get(p.key())
                 && depth > 5
                 && entry.depth <= depth
             {
-                return;
+                // The helpers would otherwise search until the time runs out
+                if thread_id == MAIN_THREAD {
+                    stop.stop_search();
+                }
+                return Some(());
             }
         }
     }
 }
 
-enum SearchRes {
-    TimeOut,
-    Leaf(i32),
-    NoPonderNode(Mv, i32),
-    Node(Mv, Mv, i32),
+/// Aspiration windows: The score rarely changes much between iterations,
+/// so we search in a small window around the last score. Smaller windows mean more cutoffs.
+/// If the search fails high / low the window is widened on that side & the root is searched again.
+/// Near mate scores (& after too many re-searches) the full window is used.
+/// Every search is reported, failed ones as a lowerbound / upperbound.
+/// Returns the score & best move, None on timeout
+fn aspiration(
+    p: &mut pos::Pos, stack: &mut Stack, stats: &mut SearchStats, stop: &Stop, prev: Option<Score>,
+    send: impl Fn(Score, tt::EntryType, Vec<Mv>, &SearchStats),
+) -> Option<(Score, Mv)> {
+    let full = (-Score::INFINITY, Score::INFINITY);
+    let mut delta = params().aspiration_window;
+    let (mut alpha, mut beta) = match prev {
+        Some(score) if stats.depth as i32 >= params().aspiration_min_depth && !score.is_mate() => {
+            (score - delta, score + delta)
+        }
+        _ => full,
+    };
+
+    loop {
+        let (score, pv) = search_root(p, stack, stats, stop, alpha, beta)?;
+        let best_mv = pv[0];
+        let bound = root_bound(score, alpha, beta);
+        send(score, bound, pv, stats);
+
+        delta *= 2;
+        match bound {
+            tt::EntryType::Exact => return Some((score, best_mv)),
+            tt::EntryType::Upper => alpha = score - delta,
+            tt::EntryType::Lower => beta = score + delta,
+        }
+        if score.is_mate() || delta >= params().aspiration_max_window {
+            (alpha, beta) = full;
+        }
+    }
 }
 
-impl SearchRes {
-    fn from_mvs(mv: (Mv, Option<Mv>), score: i32) -> SearchRes {
-        match mv.1 {
-            Some(p) => SearchRes::Node(mv.0, p, score),
-            None => SearchRes::NoPonderNode(mv.0, score),
+/// Searches the root in the window; returns the score & pv, None on timeout
+fn search_root(
+    p: &mut pos::Pos, stack: &mut Stack, stats: &mut SearchStats, stop: &Stop, alpha: Score,
+    beta: Score,
+) -> Option<(Score, Vec<Mv>)> {
+    let (score, best_mv) = match negascout(p, stats.depth, alpha, beta, stack, stats, stop) {
+        SearchRes::TimeOut => {
+            return None;
+        }
+        SearchRes::Node(mv, s) => (s, mv),
+        SearchRes::Leaf(s) => {
+            panic!("Root is a leaf Node. Score: {}", s)
         }
+    };
+
+    // The line is cut short by tt cutoffs & is empty if the root failed low
+    let mut pv = stack.pv().to_vec();
+    if pv.first().is_none_or(|mv| !mv.fuzzy_compare(&best_mv)) {
+        pv = vec![best_mv];
+    }
+    // Only send no ponder move if there really is no legal move
+    if pv.len() == 1
+        && let Some(ponder) = ponder_fallback(p, best_mv)
+    {
+        pv.push(ponder);
+    }
+    Some((score, pv))
+}
+
+pub fn legal_mvs(p: &mut pos::Pos) -> Vec<Mv> {
+    mv_gen::gen_mvs(p)
+        .into_iter()
+        .filter(|mv| is_legal(p, *mv))
+        .collect()
+}
+
+/// The tt move of the position after best_mv, otherwise any legal move
+/// None if there are no legal moves (we are about to mate / stalemate)
+fn ponder_fallback(p: &pos::Pos, mut best_mv: Mv) -> Option<Mv> {
+    let mut p = p.clone();
+    make::unchecked_make(&mut p, &mut best_mv);
+    let tt_mv = TT.get(p.key()).map(|entry| entry.mv(&p));
+    if let Some(mv) = tt_mv
+        && is_legal(&mut p, mv)
+    {
+        return Some(mv);
+    }
+    mv_gen::gen_mvs(&p)
+        .into_iter()
+        .find(|mv| is_legal(&mut p, *mv))
+}
+
+/// A root search that fails high / low only knows a bound of the real score
+fn root_bound(score: Score, alpha: Score, beta: Score) -> tt::EntryType {
+    if score <= alpha {
+        tt::EntryType::Upper
+    } else if score >= beta {
+        tt::EntryType::Lower
+    } else {
+        tt::EntryType::Exact
     }
 }
 
+/// The line below a node is saved in the stack (see PV)
+enum SearchRes {
+    TimeOut,
+    Leaf(Score),
+    Node(Mv, Score),
+}
+
 /// Main search functions; uses the optimizations described above
 fn negascout(
-    p: &mut pos::Pos, depth: u8, mut alpha: i32, mut beta: i32, stats: &mut SearchStats,
-    stop: &Stop,
+    p: &mut pos::Pos, mut depth: u8, mut alpha: Score, mut beta: Score, stack: &mut Stack,
+    stats: &mut SearchStats, stop: &Stop,
 ) -> SearchRes {
-    stats.node();
-    if p.repetitions() > 2 {
-        return SearchRes::Leaf(0);
+    stats.node(stack.ply());
+    stack.clear_pv();
+    let draw = stack.draw_score();
+    // The root has to be searched for a move, even if the position already repeated
+    if stack.ply() > 0 && p.is_repetition(stack.ply()) {
+        return SearchRes::Leaf(draw);
     }
 
-    if depth == 0 {
-        return SearchRes::Leaf(quiscence_search(p, alpha, beta));
+    // The side to move can at least force a draw
+    if stack.ply() > 0 && alpha < draw && cuckoo::upcoming_repetition(p, stack.ply()) {
+        alpha = draw;
+        if alpha >= beta {
+            return SearchRes::Leaf(alpha);
+        }
+    }
+
+    // Mate distance pruning: Even mating right now can not beat a faster mate found somewhere else
+    if stack.ply() > 0 {
+        alpha = Score::max(alpha, Score::mated_in(stack.ply()));
+        beta = Score::min(beta, Score::mate_in(stack.ply() + 1));
+        if alpha >= beta {
+            return SearchRes::Leaf(alpha);
+        }
+    }
+
+    if depth == 0 || stack.ply() >= stack::MAX_PLY {
+        return SearchRes::Leaf(quiscence_search(p, alpha, beta, stack.ply(), true, stats));
     }
 
-    let replace_entry;
     let mut tt_mv = None;
-    match parse_tt(p.key(), depth, &mut alpha, &mut beta) {
-        TtRes::Miss(replace) => replace_entry = replace,
-        TtRes::MvHint(mv, replace) => {
+    // The tt knows nothing about excluded moves
+    let tt_res = if stack.has_excluded() {
+        TtRes::Miss
+    } else {
+        parse_tt(p, depth, stack.ply(), &mut alpha, &mut beta)
+    };
+    match tt_res {
+        TtRes::Miss => {}
+        TtRes::MvHint(mv) => {
             stats.tt_hit();
-            replace_entry = replace;
-            tt_mv = Some(mv);
+            tt_mv = mv;
         }
         TtRes::Cutoff(score, mv) => {
             stats.tt_hit();
             // If we are in a pv node we dont want to cut on tt
-            if beta - alpha == 1 {
-                return SearchRes::NoPonderNode(mv, score);
+            if beta.val() - alpha.val() == 1 {
+                return match mv {
+                    Some(mv) => SearchRes::Node(mv, score),
+                    None => SearchRes::Leaf(score),
+                };
             }
             // We are in PV
-            tt_mv = Some(mv);
-            replace_entry = false;
+            tt_mv = mv;
         }
     }
 
-    if depth < 5 && stop.is_done() {
+    // Depth 1 always finishes, so there always is a bestmove
+    if stats.depth > 1 && (stats.out_of_nodes() || (stats.poll() && stop.poll())) {
         return SearchRes::TimeOut;
     }
 
-    let null_mv_return = do_null_move(p, depth, beta, tt_mv, stats, stop);
+    let in_check = make::king_in_check(p);
+    let pv_node = beta.val() - alpha.val() > 1;
+    let eval = (!in_check).then(|| Score::cp(eval::eval(p)));
+    stack.set_eval(eval);
+    let improving = stack.improving();
+    // None if no pruning is allowed in this node
+    let static_eval = eval.filter(|_| !pv_node);
+
+    if let Some(eval) = static_eval {
+        let d = depth as i32;
+        // Reverse futility pruning
+        if d <= params().rfp_max_depth && !beta.is_mate() && eval - params().rfp_margin * d >= beta
+        {
+            return SearchRes::Leaf(eval);
+        }
+
+        // Razoring
+        if d <= params().razor_max_depth && eval + params().razor_margin * d <= alpha {
+            let score = quiscence_search(p, alpha, alpha + 1, stack.ply(), true, stats);
+            if score <= alpha {
+                return SearchRes::Leaf(score);
+            }
+        }
+    }
+
+    let null_mv_return = do_null_move(p, depth, beta, static_eval, stack, stats, stop);
     if let Some(res) = null_mv_return {
         return res;
     }
 
-    // mv_gen is only called if tt_mv == None
-    // -> If tt mv produces a cutoff, we never do mv_gen
-    // This excludes the pv move from move generation to not calculate it twice
-    let mut iter = mv_gen::pv_gen_mvs_iter(p, tt_mv);
+    if static_eval.is_some()
+        && let Some(res) = probcut(p, depth, beta, tt_mv, stack, stats, stop)
+    {
+        return res;
+    }
+
+    if pv_node && tt_mv.is_none() && depth as i32 >= params().iir_min_depth {
+        depth -= 1;
+        stats.iir();
+    }
+
+    // Moves are generated lazily in stages
+    // -> If the tt mv produces a cutoff, we never do mv_gen
+    let counter_mv = match stack.prev() {
+        Some((piece, prev)) => counter::get(piece, &prev),
+        None => Mv::null(),
+    };
+    let singular_mv = match tt_mv {
+        Some(mv) => match is_singular(p, depth, mv, stack, stats, stop) {
+            None => return SearchRes::TimeOut,
+            Some(true) => mv,
+            Some(false) => Mv::null(),
+        },
+        None => Mv::null(),
+    };
+    let mut picker = MovePicker::new(tt_mv, stack.killers(), counter_mv, stack.cont());
+    // Moves that did not produce a cutoff, they get a history malus
+    let mut tried = MvList::new();
 
     let mut score;
-    let mut best_mvs: (Mv, Option<Mv>);
+    let mut best_mv: Mv;
     let mut node_type = tt::EntryType::Upper;
 
     // Only the first move!
     loop {
-        let mut pv = match iter.next() {
+        let mut pv = match next_mv(&mut picker, p, stack) {
             Some(mv) => mv,
-            None => return no_legal_moves(p),
+            None => return no_legal_moves(p, stack),
         };
         // Process PV move
         let (legal, pv_guard) = make::make(p, &mut pv);
@@ -252,140 +544,319 @@ fn negascout(
             continue;
         }
 
-        best_mvs = (pv, None);
+        best_mv = pv;
+        let extend = extension(p, &pv, singular_mv == pv, stack);
 
-        match negascout(p, depth - 1, -beta, -alpha, stats, stop) {
+        stack.push(pv, p.piece_at_sq(pv.sq().1));
+        let ext = (extend && stack.extend(stats.depth)) as u8;
+        let res = negascout(p, depth - 1 + ext, -beta, -alpha, stack, stats, stop);
+        stack.pop();
+        match res {
             SearchRes::TimeOut => {
                 make::unmake(p, pv, pv_guard);
                 return SearchRes::TimeOut;
             }
-            SearchRes::Node(ponder, _, s) | SearchRes::NoPonderNode(ponder, s) => {
-                best_mvs = (pv, Some(ponder));
-                score = -s;
-            }
-            SearchRes::Leaf(s) => score = -s,
+            SearchRes::Node(_, s) | SearchRes::Leaf(s) => score = -s,
         }
         make::unmake(p, pv, pv_guard);
 
         if score > alpha {
             alpha = score;
             node_type = tt::EntryType::Exact;
+            stack.update_pv(pv);
         }
 
         if score >= beta {
-            history::set(&pv, p.clr(), depth);
-
-            if replace_entry {
+            update_cut_heuristics(p, pv, depth, stack, &tried);
+            if !stack.has_excluded() {
                 TT.set(tt::Entry::new(
                     p.key(),
-                    alpha,
+                    alpha.to_tt(stack.ply()),
                     pv,
                     depth,
                     tt::EntryType::Lower,
                 ));
             }
-            return SearchRes::from_mvs(best_mvs, alpha);
+            return SearchRes::Node(best_mv, alpha);
         }
 
+        tried.push(pv);
         break;
     }
 
     // Check the rest of the moves using scout
-    let mut lmr_stable = true;
-    for (i, mut m) in iter.enumerate() {
+    // Legal moves searched so far
+    let mut mv_count = 1;
+    let killers = stack.killers();
+    while let Some(mut m) = next_mv(&mut picker, p, stack) {
+        let is_quiet = !m.is_cap() && !m.is_prom();
+        let history = if is_quiet {
+            history::quiet_score(&m, picker::moving_piece(p, &m), &stack.cont())
+        } else {
+            0
+        };
+
         let (legal, make_guard) = make::make(p, &mut m);
         if legal == make::Legal::ILLEGAL {
             make::unmake(p, m, make_guard);
             continue;
         }
 
-        let mut response = None;
-        // Null window search & Late move reduction
-        if do_lmr(lmr_stable, depth, i) {
-            let reduced_depth = late_move_reduction(depth);
-            match negascout(p, reduced_depth, -alpha - 1, -alpha, stats, stop) {
-                SearchRes::TimeOut => {
-                    make::unmake(p, m, make_guard);
-                    return SearchRes::TimeOut;
-                }
-                SearchRes::Node(res, _, s) | SearchRes::NoPonderNode(res, s) => {
-                    response = Some(res);
-                    score = -s;
-                }
-                SearchRes::Leaf(s) => score = -s,
+        let gives_check = make::king_in_check(p);
+        if let Some(eval) = static_eval
+            && is_quiet
+            && !alpha.is_mate()
+            && !gives_check
+            && prune_quiet(depth, mv_count, eval, alpha, history)
+        {
+            make::unmake(p, m, make_guard);
+            continue;
+        }
+        mv_count += 1;
+        let extend = extension(p, &m, singular_mv == m, stack);
+
+        stack.push(m, p.piece_at_sq(m.sq().1));
+        let ext = (extend && stack.extend(stats.depth)) as u8;
+        let new_depth = depth - 1 + ext;
+        // Late move reduction (extended moves are never reduced)
+        let mut reduction = 0;
+        if ext == 0
+            && depth as i32 >= params().lmr_min_depth
+            && mv_count as i32 > params().lmr_moves
+            && !in_check
+        {
+            let is_killer = killers
+                .iter()
+                .chain([&counter_mv])
+                .any(|k| k.fuzzy_compare(&m));
+            let less = [pv_node, improving, is_killer, gives_check, !is_quiet];
+            let r = lmr_reduction(depth, mv_count, history, &less);
+            // Always search at least depth 1
+            reduction = r.clamp(0, (new_depth as i32 - 1).max(0)) as u8;
+        }
+
+        // Null window search (at the reduced depth)
+        score = match negascout(
+            p,
+            new_depth - reduction,
+            -alpha - 1,
+            -alpha,
+            stack,
+            stats,
+            stop,
+        ) {
+            SearchRes::TimeOut => {
+                stack.pop();
+                make::unmake(p, m, make_guard);
+                return SearchRes::TimeOut;
             }
-        } else {
-            // Not reduced depth null window
-            match negascout(p, depth - 1, -alpha - 1, -alpha, stats, stop) {
+            SearchRes::Node(_, s) | SearchRes::Leaf(s) => -s,
+        };
+
+        // The reduced search beat alpha -> Null window at full depth
+        if reduction > 0 && score > alpha {
+            score = match negascout(p, new_depth, -alpha - 1, -alpha, stack, stats, stop) {
                 SearchRes::TimeOut => {
+                    stack.pop();
                     make::unmake(p, m, make_guard);
                     return SearchRes::TimeOut;
                 }
-                SearchRes::Node(res, _, s) | SearchRes::NoPonderNode(res, s) => {
-                    response = Some(res);
-                    score = -s;
-                }
-                SearchRes::Leaf(s) => score = -s,
-            }
+                SearchRes::Node(_, s) | SearchRes::Leaf(s) => -s,
+            };
         }
 
-        if alpha < score && score < beta {
-            // Unstable Node -> Dont do LMR
-            lmr_stable = false;
-            // Failed high -> Full re-search
-            match negascout(p, depth - 1, -beta, -score, stats, stop) {
+        // Failed high in a PV node -> Full window re-search
+        if pv_node && alpha < score && score < beta {
+            score = match negascout(p, new_depth, -beta, -alpha, stack, stats, stop) {
                 SearchRes::TimeOut => {
+                    stack.pop();
                     make::unmake(p, m, make_guard);
                     return SearchRes::TimeOut;
                 }
-                SearchRes::Node(res, _, s) | SearchRes::NoPonderNode(res, s) => {
-                    response = Some(res);
-                    score = -s;
-                }
-                SearchRes::Leaf(s) => score = -s,
-            }
+                SearchRes::Node(_, s) | SearchRes::Leaf(s) => -s,
+            };
         }
 
+        stack.pop();
+
         if score > alpha {
             alpha = score;
-            best_mvs = (m, response);
+            best_mv = m;
             node_type = tt::EntryType::Exact;
+            stack.update_pv(m);
         }
 
         if score >= beta {
             // Cut Node
             node_type = tt::EntryType::Lower;
             make::unmake(p, m, make_guard);
-            history::set(&m, p.clr(), depth);
+            update_cut_heuristics(p, m, depth, stack, &tried);
             break; // Prune :)
         }
 
         make::unmake(p, m, make_guard);
+        tried.push(m);
+    }
+
+    if !stack.has_excluded() {
+        TT.set(tt::Entry::new(
+            p.key(),
+            alpha.to_tt(stack.ply()),
+            best_mv,
+            depth,
+            node_type,
+        ));
+    }
+    SearchRes::Node(best_mv, alpha)
+}
+
+/// Should the move that was just made be extended (see Extensions)
+/// The stack decides if the path still has budget for it
+#[inline(always)]
+fn extension(p: &pos::Pos, m: &Mv, singular: bool, stack: &Stack) -> bool {
+    // The previous move of this node, the stack is only pushed after this
+    let recapture = RECAPTURE_EXTENSION
+        && m.is_cap()
+        && stack
+            .prev()
+            .is_some_and(|(_, prev)| prev.is_cap() && prev.sq().1 == m.sq().1);
+    singular
+        || make::king_in_check(p)
+        || recapture
+        || (PAWN_PUSH_EXTENSION && seventh_rank_push(p, m))
+}
+
+/// A pawn (of the side that just moved) reached the seventh rank
+#[inline(always)]
+fn seventh_rank_push(p: &pos::Pos, m: &Mv) -> bool {
+    let clr = p.clr().flip();
+    let end = m.sq().1;
+    let seventh = if clr.is_white() { 6 } else { 1 };
+    end / 8 == seventh && p.piece_at_sq(end) == Some(Piece::Pawn.clr(clr))
+}
+
+/// Singular extension: Searches all moves except the tt move with a reduced depth.
+/// If none of them gets close to the tt score, the tt move is singular
+/// None on timeout
+#[inline(always)]
+fn is_singular(
+    p: &mut pos::Pos, depth: u8, tt_mv: Mv, stack: &mut Stack, stats: &mut SearchStats, stop: &Stop,
+) -> Option<bool> {
+    // No nested singular searches
+    if (depth as i32) < params().se_min_depth || stack.ply() == 0 || stack.has_excluded() {
+        return Some(false);
+    }
+    let Some(entry) = TT.get(p.key()) else {
+        return Some(false);
+    };
+    let tt_score = Score::from_tt(entry.score, stack.ply());
+    if (entry.depth as i32 + params().se_tt_depth) < depth as i32
+        || entry.node_type == tt::EntryType::Upper
+        || tt_score.is_mate()
+    {
+        return Some(false);
     }
 
-    if replace_entry {
-        TT.set(tt::Entry::new(p.key(), alpha, best_mvs.0, depth, node_type));
+    let singular_beta = tt_score - params().se_margin * depth as i32;
+    stack.exclude_mv(tt_mv);
+    let res = negascout(
+        p,
+        (depth - 1) / 2,
+        singular_beta - 1,
+        singular_beta,
+        stack,
+        stats,
+        stop,
+    );
+    stack.exclude_mv(Mv::null());
+    // The search used the same ply
+    stack.clear_pv();
+    match res {
+        SearchRes::TimeOut => None,
+        SearchRes::Node(_, score) | SearchRes::Leaf(score) => Some(score < singular_beta),
     }
-    return SearchRes::from_mvs(best_mvs, alpha);
 }
 
+/// Futility, late move & history pruning of a quiet move that does not give check
 #[inline(always)]
-fn no_legal_moves(p: &pos::Pos) -> SearchRes {
-    let king_pos = p.piece(Piece::King.clr(p.clr())).get_ones_single();
-    if !make::square_attacked(p, p.clr(), king_pos) {
+fn prune_quiet(depth: u8, mv_count: usize, eval: Score, alpha: Score, history: i32) -> bool {
+    let d = depth as i32;
+    let params = params();
+    (d <= params.lmp_max_depth && mv_count as i32 >= params.lmp_base + d.pow(2))
+        || (d <= params.futility_max_depth
+            && eval + (params.futility_base + params.futility_margin * d) <= alpha)
+        || (d <= params.history_prune_max_depth && history < -params.history_prune_margin * d)
+}
+
+#[inline(always)]
+fn no_legal_moves(p: &pos::Pos, stack: &Stack) -> SearchRes {
+    if !make::king_in_check(p) {
         // Stalemate
-        return SearchRes::Leaf(0);
+        SearchRes::Leaf(stack.draw_score())
     } else {
         // Checkmate
-        return SearchRes::Leaf(eval::SAFE_MIN_SCORE);
+        SearchRes::Leaf(Score::mated_in(stack.ply()))
+    }
+}
+
+/// Skips excluded root moves
+#[inline(always)]
+fn next_mv(picker: &mut MovePicker, p: &pos::Pos, stack: &Stack) -> Option<Mv> {
+    loop {
+        let mv = picker.next(p)?;
+        if !stack.is_excluded(&mv) {
+            return Some(mv);
+        }
+    }
+}
+
+/// Called when a move produces a beta cutoff
+/// The cutoff move gets a history bonus, the moves of the same kind that were tried before it a malus
+/// Killer & counter moves are only saved for quiet moves, since captures are already ordered first
+#[inline(always)]
+fn update_cut_heuristics(p: &pos::Pos, m: Mv, depth: u8, stack: &mut Stack, tried: &MvList) {
+    let bonus = history::bonus(depth);
+    let is_quiet = |mv: &Mv| !mv.is_cap() && !mv.is_prom();
+
+    // Captures that did not cut are always penalized
+    for mv in tried.as_slice().iter().filter(|mv| mv.is_cap()) {
+        history::update_capture(mv, picker::moving_piece(p, mv), -bonus);
+    }
+
+    if !is_quiet(&m) {
+        history::update_capture(&m, picker::moving_piece(p, &m), bonus);
+        return;
+    }
+
+    let cont = stack.cont();
+    history::update_quiet(&m, picker::moving_piece(p, &m), &cont, bonus);
+    for mv in tried.as_slice().iter().filter(|mv| is_quiet(mv)) {
+        history::update_quiet(mv, picker::moving_piece(p, mv), &cont, -bonus);
+    }
+
+    stack.set_killer(m);
+    if let Some((piece, prev)) = stack.prev() {
+        counter::set(piece, &prev, m);
     }
 }
 
 #[inline(always)]
 fn do_null_move(
-    p: &mut pos::Pos, depth: u8, beta: i32, tt_mv: Option<Mv>, stats: &mut SearchStats, stop: &Stop,
+    p: &mut pos::Pos, depth: u8, beta: Score, eval: Option<Score>, stack: &mut Stack,
+    stats: &mut SearchStats, stop: &Stop,
 ) -> Option<SearchRes> {
-    if depth < 4 {
+    // The root has to return a move (& a bound with an aspiration window)
+    // No eval -> In check or pv node
+    // A singular verification search has to score the other moves, not the position
+    let eval = eval?;
+    if (depth as i32) < params().nmp_min_depth
+        || stack.ply() == 0
+        || eval < beta
+        || beta.is_mate()
+        || !stack.null_allowed()
+        || stack.has_excluded()
+        || only_pawns(p)
+    {
         return None;
     }
 
@@ -395,99 +866,242 @@ fn do_null_move(
         return None;
     }
 
-    let null_score;
-    match negascout(p, depth - 3, -beta, -(beta - 1), stats, stop) {
-        SearchRes::TimeOut => {
-            make::unmake_null(p, was_ep, null_guard);
-            return Some(SearchRes::TimeOut);
+    let eval_reduction =
+        ((eval.val() - beta.val()) / params().nmp_eval_margin).min(params().nmp_max_eval_reduction);
+    let reduction = params().nmp_base + depth as i32 / params().nmp_depth_divisor + eval_reduction;
+    let null_depth = (depth as i32 - reduction).max(0) as u8;
+    stack.push(Mv::null(), None);
+    let res = negascout(p, null_depth, -beta, -(beta - 1), stack, stats, stop);
+    stack.pop();
+    make::unmake_null(p, was_ep, null_guard);
+    let null_score = match res {
+        SearchRes::TimeOut => return Some(SearchRes::TimeOut),
+        SearchRes::Node(_, score) | SearchRes::Leaf(score) => -score,
+    };
+
+    // Even if we dont make a move we are still outside of the window
+    if null_score < beta {
+        return None;
+    }
+    // The null move can not prove a mate
+    let score = if null_score.is_mate() {
+        beta
+    } else {
+        null_score
+    };
+    if (depth as i32) < params().nmp_verify_depth {
+        return Some(SearchRes::Leaf(score));
+    }
+
+    // Zugzwang verification: The same search without passing (see Null Move Pruning)
+    let prev_min_ply = stack.disable_null(stack.ply() + null_depth as usize);
+    let res = negascout(p, null_depth, beta - 1, beta, stack, stats, stop);
+    stack.disable_null(prev_min_ply);
+    // The search used the same ply
+    stack.clear_pv();
+    match res {
+        SearchRes::TimeOut => Some(SearchRes::TimeOut),
+        SearchRes::Node(_, verified) | SearchRes::Leaf(verified) => {
+            (verified >= beta).then_some(SearchRes::Leaf(score))
         }
-        SearchRes::Node(_, _, score)
-        | SearchRes::Leaf(score)
-        | SearchRes::NoPonderNode(_, score) => null_score = -score,
     }
+}
 
-    // The null move sets the baseline for what we think we can achive
-    // Even if we dont make a move we are still outside of the window
-    make::unmake_null(p, was_ep, null_guard);
-    if null_score >= beta {
-        match tt_mv {
-            Some(m) => return Some(SearchRes::NoPonderNode(m, beta)),
-            None => return Some(SearchRes::Leaf(beta)),
+/// Tries good captures against a raised beta (see ProbCut)
+/// None if the node was not cut
+#[inline(always)]
+fn probcut(
+    p: &mut pos::Pos, depth: u8, beta: Score, tt_mv: Option<Mv>, stack: &mut Stack,
+    stats: &mut SearchStats, stop: &Stop,
+) -> Option<SearchRes> {
+    if (depth as i32) < params().probcut_min_depth || beta.is_mate() || stack.has_excluded() {
+        return None;
+    }
+    let probcut_beta = beta + params().probcut_margin;
+    let probcut_depth = depth.saturating_sub(params().probcut_reduction as u8);
+    // A search that was deep enough already failed to beat the raised beta
+    if let Some(entry) = TT.get(p.key())
+        && entry.depth >= probcut_depth
+        && entry.node_type != tt::EntryType::Lower
+        && Score::from_tt(entry.score, stack.ply()) < probcut_beta
+    {
+        return None;
+    }
+
+    // The picker only checks the other captures with SEE
+    let tt_mv = tt_mv.filter(|mv| mv.is_cap() && see::see(p, mv) >= 0);
+    let mut picker = MovePicker::new_noisy(tt_mv, false);
+    while let Some(mut m) = picker.next(p) {
+        if !m.is_cap() {
+            continue;
+        }
+        let (legal, make_guard) = make::make(p, &mut m);
+        if legal == Legal::ILLEGAL {
+            make::unmake(p, m, make_guard);
+            continue;
+        }
+
+        stack.push(m, p.piece_at_sq(m.sq().1));
+        let mut score = -quiscence_search(
+            p,
+            -probcut_beta,
+            -probcut_beta + 1,
+            stack.ply(),
+            true,
+            stats,
+        );
+        if score >= probcut_beta {
+            let res = negascout(
+                p,
+                probcut_depth,
+                -probcut_beta,
+                -probcut_beta + 1,
+                stack,
+                stats,
+                stop,
+            );
+            score = match res {
+                SearchRes::TimeOut => {
+                    stack.pop();
+                    make::unmake(p, m, make_guard);
+                    return Some(SearchRes::TimeOut);
+                }
+                SearchRes::Node(_, s) | SearchRes::Leaf(s) => -s,
+            };
+        }
+        stack.pop();
+        make::unmake(p, m, make_guard);
+
+        if score >= probcut_beta {
+            stats.probcut();
+            TT.set(tt::Entry::new(
+                p.key(),
+                score.to_tt(stack.ply()),
+                m,
+                probcut_depth + 1,
+                tt::EntryType::Lower,
+            ));
+            return Some(SearchRes::Node(m, score));
         }
     }
-    return None;
+    None
+}
+
+/// Contempt of the root side in centipawns (see Contempt)
+fn root_contempt(p: &pos::Pos) -> i32 {
+    let contempt = CONTEMPT.load(Ordering::Relaxed);
+    if contempt == 0 {
+        return 0;
+    }
+    let eval = eval::eval(p);
+    let contempt = contempt + CONTEMPT_EVAL_SCALE * eval / (eval.abs() + CONTEMPT_EVAL_HALF);
+    contempt * (2 * eval::ENDGAME_PHASE - eval::phase(p)) / (2 * eval::ENDGAME_PHASE)
+}
+
+/// The side to move only has pawns & the king (zugzwang is likely)
+#[inline(always)]
+fn only_pawns(p: &pos::Pos) -> bool {
+    [Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen]
+        .iter()
+        .all(|piece| p.piece(piece.clr(p.clr())).val() == 0)
 }
 
-enum TtRes {
-    Miss(bool),
-    Cutoff(i32, Mv),
-    MvHint(Mv, bool),
+pub enum TtRes {
+    Miss,
+    Cutoff(Score, Option<Mv>),
+    MvHint(Option<Mv>),
 }
 
 /// Reading from the transposition table.
 /// Split into its own function to decrease complexity of the negascout function
+/// The tt mv is None if it is not pseudo legal in this position (key collision)
 #[inline(always)]
-fn parse_tt(key: tt::Key, depth: u8, alpha: &mut i32, beta: &mut i32) -> TtRes {
-    let entry = match TT.get(key) {
-        None => return TtRes::Miss(true),
+pub fn parse_tt(p: &pos::Pos, depth: u8, ply: usize, alpha: &mut Score, beta: &mut Score) -> TtRes {
+    let entry = match TT.get(p.key()) {
+        None => return TtRes::Miss,
         Some(e) => e,
     };
 
-    if entry.key != key {
-        let replace = entry.depth < depth;
-        return TtRes::Miss(replace);
-    }
+    let score = Score::from_tt(entry.score, ply);
+    let mv = Some(entry.mv(p)).filter(|mv| mv_gen::is_pseudo_legal(p, mv));
 
     if entry.depth < depth {
         // The Entry knows less than we want to known
         // -> Still use PV move for move ordering
-        return TtRes::MvHint(entry.mv, true);
+        return TtRes::MvHint(mv);
     }
 
     match entry.node_type {
         // The Node is at a greater depth && exact -> Just use that value
-        tt::EntryType::Exact => return TtRes::Cutoff(entry.score, entry.mv),
+        tt::EntryType::Exact => TtRes::Cutoff(score, mv),
         tt::EntryType::Upper => {
-            if entry.score <= *alpha {
-                return TtRes::Cutoff(entry.score, entry.mv);
+            if score <= *alpha {
+                return TtRes::Cutoff(score, mv);
             }
             // We have a better upper bound
-            if entry.score < *beta {
-                *beta = entry.score;
+            if score < *beta {
+                *beta = score;
             }
-            return TtRes::MvHint(entry.mv, false);
+            TtRes::MvHint(mv)
         }
 
         tt::EntryType::Lower => {
-            if entry.score >= *beta {
-                return TtRes::Cutoff(entry.score, entry.mv);
+            if score >= *beta {
+                return TtRes::Cutoff(score, mv);
             }
             // We have a better lower bound
-            if entry.score > *alpha {
-                *alpha = entry.score;
+            if score > *alpha {
+                *alpha = score;
             }
-            return TtRes::MvHint(entry.mv, false);
+            TtRes::MvHint(mv)
         }
     }
 }
 
-/// How many moves to do before starting late move reductions
-const LMR_MOVES: usize = 2;
+/// Pseudo legal & does not leave the king in check
+fn is_legal(p: &mut pos::Pos, mut mv: Mv) -> bool {
+    if !mv_gen::is_pseudo_legal(p, &mv) {
+        return false;
+    }
+    let (legal, guard) = make::make(p, &mut mv);
+    make::unmake(p, mv, guard);
+    legal == Legal::LEGAL
+}
+
+const LMR_TABLE_SIZE: usize = 64;
 
-/// Calc LMR reduction depending on depth
-/// Very basic right now; subject to change
-#[inline(always)]
-fn late_move_reduction(depth: u8) -> u8 {
-    if depth < 6 {
-        depth - 1
-    } else {
-        depth - (depth / 3)
+static mut LMR_TABLE: [[u8; LMR_TABLE_SIZE]; LMR_TABLE_SIZE] =
+    [[0; LMR_TABLE_SIZE]; LMR_TABLE_SIZE];
+
+/// Base reduction by depth & move number: lmr_base + ln(depth) * ln(move number) / lmr_divisor
+/// Called again if the parameters change (see params)
+pub fn init_lmr() {
+    let base = params().lmr_base as f64 / 100.0;
+    let divisor = params().lmr_divisor as f64 / 100.0;
+    let mut table = [[0; LMR_TABLE_SIZE]; LMR_TABLE_SIZE];
+    for (depth, row) in table.iter_mut().enumerate().skip(1) {
+        for (mv_count, r) in row.iter_mut().enumerate().skip(1) {
+            *r = (base + (depth as f64).ln() * (mv_count as f64).ln() / divisor) as u8;
+        }
     }
+    // Only called on init & while no search is running
+    unsafe { LMR_TABLE = table };
+}
+
+#[inline(always)]
+pub fn lmr_table(depth: u8, mv_count: usize) -> i32 {
+    let depth = (depth as usize).min(LMR_TABLE_SIZE - 1);
+    let mv_count = mv_count.min(LMR_TABLE_SIZE - 1);
+    // Only written while no search is running
+    unsafe { LMR_TABLE[depth][mv_count] as i32 }
 }
 
+/// The reduction of a late move, before it is clamped to the depth (see Late move reduction)
+/// less: Every true flag (pv node, improving, killer, check, ...) reduces one ply less
 #[inline(always)]
-fn do_lmr(do_lmr: bool, depth: u8, i: usize) -> bool {
-    do_lmr && depth > 2 && i >= LMR_MOVES
+pub fn lmr_reduction(depth: u8, mv_count: usize, history: i32, less: &[bool]) -> i32 {
+    let less = less.iter().filter(|flag| **flag).count() as i32;
+    lmr_table(depth, mv_count) - less - history / params().lmr_history_divisor
 }
 
 pub fn debug_division_search(p: &mut pos::Pos, depth: u8) {
@@ -0,0 +1,257 @@
+//! # Search Stack
+//! Keeps track of the line from the root to the node that is currently searched.
+//! Every ply saves the move (& moving piece) that was played to reach it,
+//! as well as the killer moves found at that ply.
+//! ## Killer Heuristic
+//! Quiet moves that produced a beta cutoff at the same ply in a sibling node
+//! are likely to also refute the current move. We save the two most recent ones per ply.
+//! ## Principal Variation
+//! The PV is collected in a triangular table: every ply saves its best line,
+//! which is its best move followed by the line of the child.
+//! The root line is the full PV, its second move is the ponder move.
+//! ## Excluded Root Moves
+//! MultiPV searches the root once per line, excluding the best moves of the previous lines.
+//! `go searchmoves` excludes every root move that is not in the list.
+//! ## Extensions
+//! Every ply counts the extensions on the path from the root, a move is only extended while the path is under its budget.
+//! Singular extension searches exclude the TT move at their ply.
+//! ## Improving
+//! The static eval of every ply is saved. If it is better than two plies ago (same side to move),
+//! the position is improving & moves are reduced less (see search).
+//! ## Null Moves
+//! Two null moves in a row would just pass the turn back.
+//! The zugzwang verification search disables null moves for the plies it searches.
+//! ## Draw Score
+//! The contempt is saved from the view of the root side, every ply flips it.
+
+use rosa_lib::history::PieceTo;
+use rosa_lib::mv::Mv;
+use rosa_lib::piece::ClrPiece;
+use rosa_lib::score::Score;
+
+pub const MAX_PLY: usize = 128;
+
+#[derive(Clone, Copy)]
+struct Frame {
+    mv: Mv,
+    piece: Option<ClrPiece>,
+    killers: [Mv; 2],
+    /// Extensions from the root to this ply
+    extensions: u8,
+    /// Skipped by singular extension searches
+    excluded: Mv,
+    /// None while in check
+    eval: Option<Score>,
+}
+
+impl Frame {
+    const EMPTY: Frame = Frame {
+        mv: Mv::null(),
+        piece: None,
+        killers: [Mv::null(); 2],
+        extensions: 0,
+        excluded: Mv::null(),
+        eval: None,
+    };
+}
+
+pub struct Stack {
+    frames: [Frame; MAX_PLY + 1],
+    ply: usize,
+    // Ply x line, the line at ply n is at most MAX_PLY - n long
+    pv: Box<[[Mv; MAX_PLY + 1]]>,
+    pv_len: [usize; MAX_PLY + 2],
+    root_excluded: Vec<Mv>,
+    /// Empty if all root moves are allowed
+    root_mvs: Vec<Mv>,
+    /// No null moves before this ply (verification search)
+    null_min_ply: usize,
+    /// Centipawns the root side loses with a draw
+    contempt: i32,
+}
+
+impl Default for Stack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stack {
+    pub fn new() -> Stack {
+        Stack {
+            frames: [Frame::EMPTY; MAX_PLY + 1],
+            ply: 0,
+            pv: vec![[Mv::null(); MAX_PLY + 1]; MAX_PLY + 1].into_boxed_slice(),
+            pv_len: [0; MAX_PLY + 2],
+            root_excluded: Vec::new(),
+            root_mvs: Vec::new(),
+            null_min_ply: 0,
+            contempt: 0,
+        }
+    }
+
+    pub fn ply(&self) -> usize {
+        self.ply
+    }
+
+    /// Has to be called after the move was made, but before searching the child node.
+    /// Null moves are pushed as Mv::null() with no piece
+    pub fn push(&mut self, mv: Mv, piece: Option<ClrPiece>) {
+        debug_assert!(self.ply < MAX_PLY);
+        let extensions = self.frames[self.ply].extensions;
+        self.ply += 1;
+        let frame = &mut self.frames[self.ply];
+        frame.mv = mv;
+        frame.piece = piece;
+        frame.extensions = extensions;
+        frame.excluded = Mv::null();
+        frame.eval = None;
+    }
+
+    /// Extends the move that lead to the current node, unless the path used up the budget
+    /// Returns true if the move was extended
+    pub fn extend(&mut self, budget: u8) -> bool {
+        let frame = &mut self.frames[self.ply];
+        if frame.extensions >= budget {
+            return false;
+        }
+        frame.extensions += 1;
+        true
+    }
+
+    pub fn extensions(&self) -> u8 {
+        self.frames[self.ply].extensions
+    }
+
+    pub fn set_eval(&mut self, eval: Option<Score>) {
+        self.frames[self.ply].eval = eval;
+    }
+
+    /// False if there is no eval to compare to
+    pub fn improving(&self) -> bool {
+        if self.ply < 2 {
+            return false;
+        }
+        match (self.frames[self.ply].eval, self.frames[self.ply - 2].eval) {
+            (Some(eval), Some(prev)) => eval > prev,
+            _ => false,
+        }
+    }
+
+    /// The last move was not a null move & null moves are not disabled at this ply
+    pub fn null_allowed(&self) -> bool {
+        self.ply >= self.null_min_ply && !(self.ply > 0 && self.frames[self.ply].mv.is_null())
+    }
+
+    /// 0 to allow null moves again; returns the previous value, so it can be restored
+    pub fn disable_null(&mut self, until_ply: usize) -> usize {
+        std::mem::replace(&mut self.null_min_ply, until_ply)
+    }
+
+    pub fn set_contempt(&mut self, contempt: i32) {
+        self.contempt = contempt;
+    }
+
+    /// From the view of the side to move
+    pub fn draw_score(&self) -> Score {
+        if self.ply.is_multiple_of(2) {
+            Score::cp(-self.contempt)
+        } else {
+            Score::cp(self.contempt)
+        }
+    }
+
+    /// Mv::null() to stop excluding
+    pub fn exclude_mv(&mut self, mv: Mv) {
+        self.frames[self.ply].excluded = mv;
+    }
+
+    pub fn pop(&mut self) {
+        debug_assert!(self.ply > 0);
+        self.ply -= 1;
+    }
+
+    /// The move (& the piece that moved) that lead to the current node
+    /// None at the root & after null moves
+    pub fn prev(&self) -> Option<(ClrPiece, Mv)> {
+        let frame = &self.frames[self.ply];
+        frame.piece.map(|piece| (piece, frame.mv))
+    }
+
+    /// The last two moves as (piece, to sq), for the continuation history
+    pub fn cont(&self) -> [Option<PieceTo>; 2] {
+        let piece_to = |frame: &Frame| frame.piece.map(|piece| (piece, frame.mv.sq().1));
+        let prev = piece_to(&self.frames[self.ply]);
+        if self.ply == 0 {
+            return [prev, None];
+        }
+        [prev, piece_to(&self.frames[self.ply - 1])]
+    }
+
+    pub fn killers(&self) -> [Mv; 2] {
+        self.frames[self.ply].killers
+    }
+
+    /// Has to be called when entering a node
+    pub fn clear_pv(&mut self) {
+        self.pv_len[self.ply] = 0;
+    }
+
+    /// mv is the new best move of the current node, followed by the line of the child
+    pub fn update_pv(&mut self, mv: Mv) {
+        let ply = self.ply;
+        let child_len = if ply < MAX_PLY {
+            self.pv_len[ply + 1]
+        } else {
+            0
+        };
+        let (line, child) = self.pv.split_at_mut(ply + 1);
+        let line = &mut line[ply];
+        line[0] = mv;
+        if child_len > 0 {
+            line[1..=child_len].copy_from_slice(&child[0][..child_len]);
+        }
+        self.pv_len[ply] = child_len + 1;
+    }
+
+    /// The best line from the root
+    pub fn pv(&self) -> &[Mv] {
+        &self.pv[0][..self.pv_len[0]]
+    }
+
+    pub fn exclude_root_mv(&mut self, mv: Mv) {
+        self.root_excluded.push(mv);
+    }
+
+    pub fn clear_root_excluded(&mut self) {
+        self.root_excluded.clear();
+    }
+
+    pub fn restrict_root(&mut self, mvs: Vec<Mv>) {
+        self.root_mvs = mvs;
+    }
+
+    /// The TT knows nothing about excluded moves
+    pub fn has_excluded(&self) -> bool {
+        !self.frames[self.ply].excluded.is_null()
+            || (self.ply == 0 && !(self.root_excluded.is_empty() && self.root_mvs.is_empty()))
+    }
+
+    pub fn is_excluded(&self, mv: &Mv) -> bool {
+        let excluded = self.frames[self.ply].excluded;
+        if !excluded.is_null() && excluded.fuzzy_compare(mv) {
+            return true;
+        }
+        self.ply == 0
+            && (self.root_excluded.iter().any(|m| m.fuzzy_compare(mv))
+                || !(self.root_mvs.is_empty() || self.root_mvs.iter().any(|m| m.fuzzy_compare(mv))))
+    }
+
+    pub fn set_killer(&mut self, mv: Mv) {
+        let killers = &mut self.frames[self.ply].killers;
+        if !killers[0].fuzzy_compare(&mv) {
+            killers[1] = killers[0];
+            killers[0] = mv;
+        }
+    }
+}
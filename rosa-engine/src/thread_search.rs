@@ -1,89 +1,164 @@
 //! # Multithreaded searching
-//! Not ready for multithreading yet, setting up infrastructure
+//! Rosa uses Lazy SMP: Every thread searches the same root position using iterative deepening.
+//! The threads only communicate through the shared transposition table.
+//! Since the helper threads start at different depths and reach nodes at different times,
+//! they fill the TT with results the other threads can use.
 //! Threading Setup:
 //! One thread blocks on stdin & timeout, one blocks on pulling from the search reports
 //! Rest search
+//! ## MultiPV
+//! With MultiPV > 1 every thread searches the best k root moves,
+//! only the lines of the main thread are printed & only the first line decides the bestmove
+//! ## Holding the Bestmove
+//! Infinite & ponder searches keep the bestmove until the runtime releases it (stop or ponderhit)
+//! ## Fallback Bestmove
+//! A bestmove is always sent, even if the search was stopped before depth 1 finished:
+//! Any legal move, or the null move 0000 if there is none
 
-use crate::make;
+use crate::config;
 use crate::search;
+use crate::time::{SearchLimits, TimeManager};
 
 use crossbeam::channel;
 use rosa_lib::mv::Mv;
 use rosa_lib::pos;
+use rosa_lib::score::Score;
+use rosa_lib::tt::EntryType;
 
 use std::sync::Arc;
 use std::sync::atomic;
-use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicBool, AtomicUsize};
 use std::sync::mpsc;
 use std::thread;
+use std::time::Duration;
 
-const THREAD_COUNT: usize = 1;
+pub const MAIN_THREAD: usize = 0;
+/// Every thread recurses through the whole search path, with a move picker on every ply
+const SEARCH_STACK_SIZE: usize = 16 * config::MB as usize;
+/// How often a held bestmove checks if it was released
+const HOLD_POLL: Duration = Duration::from_millis(1);
 
-pub fn start_thread_search(p: &pos::Pos) -> (channel::Receiver<Option<Mv>>, Stop) {
+static THREAD_COUNT: AtomicUsize = AtomicUsize::new(config::DEFAULT_THREADS);
+static MULTIPV: AtomicUsize = AtomicUsize::new(config::DEFAULT_MULTIPV);
+/// uci debug on|off
+static DEBUG: AtomicBool = AtomicBool::new(false);
+
+pub fn set_thread_count(count: usize) {
+    let count = count.clamp(config::MIN_THREADS, config::MAX_THREADS);
+    THREAD_COUNT.store(count, atomic::Ordering::Relaxed);
+}
+
+pub fn set_multipv(lines: usize) {
+    let lines = lines.clamp(config::MIN_MULTIPV, config::MAX_MULTIPV);
+    MULTIPV.store(lines, atomic::Ordering::Relaxed);
+}
+
+pub fn set_debug(on: bool) {
+    DEBUG.store(on, atomic::Ordering::Relaxed);
+}
+
+pub fn start_thread_search(
+    p: &pos::Pos, limits: SearchLimits, time: TimeManager,
+) -> (channel::Receiver<Option<Mv>>, Stop) {
     let (tx, rx) = channel::unbounded();
     let p = p.clone();
-    let stop = Stop::new();
+    let stop = Stop::new(time, limits.infinite);
     let stop_c = stop.clone();
-    thread::spawn(|| thread_handler(p, tx, stop_c));
+    thread::spawn(|| thread_handler(p, tx, stop_c, limits));
     (rx, stop)
 }
 
 /// Spawns threads and start search
 /// Collects the thread reports and compiles them
-fn thread_handler(p: pos::Pos, tx: channel::Sender<Option<Mv>>, stop: Stop) {
+fn thread_handler(
+    mut p: pos::Pos, tx: channel::Sender<Option<Mv>>, stop: Stop, limits: SearchLimits,
+) {
     let start_time = std::time::Instant::now();
+    let thread_count = THREAD_COUNT.load(atomic::Ordering::Relaxed);
+    let multipv = MULTIPV.load(atomic::Ordering::Relaxed);
+    search::TT.new_search();
     let (sender, reciever) = mpsc::channel::<ThreadReport>();
-    for _ in 0..THREAD_COUNT {
+    for thread_id in 0..thread_count {
         let pclone = p.clone();
         let thread_sender = sender.clone();
         let stop_clone = stop.clone();
-        thread::spawn(move || {
-            search::search(pclone, thread_sender, stop_clone);
-        });
+        let limits_clone = limits.clone();
+        thread::Builder::new()
+            .stack_size(SEARCH_STACK_SIZE)
+            .spawn(move || {
+                search::search(
+                    pclone,
+                    thread_sender,
+                    stop_clone,
+                    thread_id,
+                    multipv,
+                    limits_clone,
+                );
+            })
+            .unwrap();
     }
     // So we properly end the while loop
     drop(sender);
-    drop(stop);
 
-    let mut total_nodes = 0;
-    let mut thread_reports: Vec<Vec<ThreadReport>> = Vec::new();
+    // The nodes of a thread count its whole search
+    let mut thread_nodes: Vec<u64> = vec![0; thread_count];
+    let mut printed = (0, -Score::INFINITY, MAIN_THREAD);
+    let mut last_reports: Vec<Option<ThreadReport>> = vec![None; thread_count];
 
     while let Ok(report) = reciever.recv() {
-        let depth = report.depth as usize;
-        while depth >= thread_reports.len() {
-            thread_reports.push(Vec::new());
-        }
-        thread_reports[depth].push(report);
-        if thread_reports[depth].len() == THREAD_COUNT {
-            // All threads have reported for this depth
-            let mut best_score = i32::MIN;
-            let mut best_pv = thread_reports[depth][0].pv;
-            let mut tt_hits = 0;
-
-            for report in &thread_reports[depth] {
-                if report.score > best_score {
-                    best_score = report.score;
-                    best_pv = report.pv;
-                }
-                total_nodes += report.stats.nodes;
-                tt_hits += report.stats.tt_hits;
-            }
+        thread_nodes[report.thread] = report.stats.nodes;
+        let total_nodes = thread_nodes.iter().sum();
 
-            print_info(
-                best_pv,
-                best_score,
-                depth as u8,
-                total_nodes,
-                tt_hits,
-                start_time,
-            );
+        if multipv > 1 {
+            // The lines of different threads would be mixed up
+            if report.thread == MAIN_THREAD {
+                print_info(&report, total_nodes, start_time);
+            }
+        } else if (report.depth, report.score) > (printed.0, printed.1)
+            || (report.depth, report.thread) == (printed.0, printed.2)
+        {
+            // Only report a depth once, no matter which thread finished it first,
+            // unless another thread found a better score at the same depth (-> same order as best_report())
+            // or the same thread had to re-search (aspiration windows)
+            printed = (report.depth, report.score, report.thread);
+            print_info(&report, total_nodes, start_time);
+        }
+        // A fail low does not know a good move, a fail high at least knows one
+        if report.multipv != 1 || report.bound == EntryType::Upper {
+            continue;
         }
+        let thread = report.thread;
+        last_reports[thread] = Some(report);
     }
 
-    let report = thread_reports.last().unwrap().first().unwrap();
-    let mut pv = report.pv;
+    while stop.is_held() {
+        thread::sleep(HOLD_POLL);
+    }
 
-    match report.ponder {
+    let Some(report) = best_report(&last_reports) else {
+        // Searchmoves are preferred, unless they are all illegal (see search)
+        let fallback = search::legal_mvs(&mut p)
+            .into_iter()
+            .min_by_key(|mv| !limits.searchmoves.contains(&mv.to_string()));
+        match fallback {
+            Some(mv) => println!("bestmove {mv}"),
+            None => println!("bestmove 0000"),
+        }
+        tx.send(None).unwrap();
+        return;
+    };
+    if DEBUG.load(atomic::Ordering::Relaxed) {
+        println!(
+            "info string thread {} tt hits {} probcuts {} iir {}",
+            report.thread,
+            report.stats.tt_hits,
+            report.stats.probcuts,
+            report.stats.internal_reductions
+        );
+    }
+    let pv = report.pv[0];
+
+    match report.pv.get(1).copied() {
         Some(pon) => {
             println!("bestmove {} ponder {}", pv, pon);
             tx.send(Some(pon)).unwrap();
@@ -94,92 +169,203 @@ fn thread_handler(p: pos::Pos, tx: channel::Sender<Option<Mv>>, stop: Stop) {
             // -> Quit unlikely
             // b) We have just played a checkmating move
             // -> The resulting position has no legal moves
-            let mut p_after_move = p.clone();
-            make::make(&mut p_after_move, &mut pv);
             println!("bestmove {}", pv);
             tx.send(None).unwrap();
         }
     }
 }
 
-fn print_info(
-    pv: Mv, score: i32, depth: u8, nodes: u64, tt_hits: u64, start_time: std::time::Instant,
-) {
-    let finish_time = std::time::Instant::now();
+/// The deepest finished iteration wins, the score decides between threads of the same depth
+/// On a complete tie the main thread is preferred
+fn best_report(reports: &[Option<ThreadReport>]) -> Option<&ThreadReport> {
+    reports
+        .iter()
+        .rev()
+        .flatten()
+        .max_by_key(|report| (report.depth, report.score))
+}
+
+/// The pv has to be last, since it takes up the rest of the line
+fn print_info(report: &ThreadReport, nodes: u64, start_time: std::time::Instant) {
+    let millis = start_time.elapsed().as_millis();
+    let bound = match report.bound {
+        EntryType::Exact => "",
+        EntryType::Lower => " lowerbound",
+        EntryType::Upper => " upperbound",
+    };
+    let pv: Vec<String> = report.pv.iter().map(|mv| mv.to_string()).collect();
     println!(
-        "info depth {} pv {} time {} score cp {} nodes {}, nps {}, tbhits {}",
-        depth,
-        pv,
-        finish_time.duration_since(start_time).as_millis(),
-        score,
+        "info depth {} seldepth {} multipv {} score {}{} nodes {} nps {} hashfull {} time {} pv {}",
+        report.depth,
+        report.stats.seldepth,
+        report.multipv,
+        report.score,
+        bound,
         nodes,
-        (nodes / finish_time.duration_since(start_time).as_millis().max(1) as u64) * 1000,
-        tt_hits,
+        (nodes / millis.max(1) as u64) * 1000,
+        search::TT.hashfull(),
+        millis,
+        pv.join(" "),
     )
 }
 
 #[derive(Clone)]
 pub struct ThreadReport {
+    thread: usize,
+    /// Starts at 1 for the best line
+    multipv: usize,
     depth: u8,
-    score: i32,
-    pv: Mv,
-    ponder: Option<Mv>,
+    score: Score,
+    bound: EntryType,
+    /// Never empty, the second move is the ponder move
+    pv: Vec<Mv>,
     stats: SearchStats,
 }
 
 impl ThreadReport {
-    pub fn new(depth: u8, score: i32, pv: Mv, ponder: Option<Mv>, stats: SearchStats) -> Self {
+    pub fn new(
+        thread: usize, multipv: usize, depth: u8, score: Score, bound: EntryType, pv: Vec<Mv>,
+        stats: SearchStats,
+    ) -> Self {
+        debug_assert!(!pv.is_empty());
         ThreadReport {
+            thread,
+            multipv,
             depth,
             score,
+            bound,
             pv,
-            ponder,
             stats,
         }
     }
+
+    pub fn score(&self) -> Score {
+        self.score
+    }
+
+    pub fn pv(&self) -> &[Mv] {
+        &self.pv
+    }
+
+    pub fn stats(&self) -> &SearchStats {
+        &self.stats
+    }
 }
 
 #[derive(Clone)]
 pub struct SearchStats {
     pub depth: u8,
+    /// Deepest ply reached (including quiscence search)
+    pub seldepth: usize,
     nodes: u64,
     tt_hits: u64,
+    node_limit: u64,
+    /// Nodes cut by ProbCut
+    pub probcuts: u64,
+    /// Internal iterative reductions
+    pub internal_reductions: u64,
 }
 
 impl SearchStats {
     pub fn new(depth: u8) -> Self {
         SearchStats {
             depth,
+            seldepth: 0,
             nodes: 0,
             tt_hits: 0,
+            node_limit: u64::MAX,
+            probcuts: 0,
+            internal_reductions: 0,
         }
     }
 
-    pub fn node(&mut self) {
+    /// Nodes & tt hits are counted over the whole search
+    pub fn new_iteration(&mut self, depth: u8) {
+        self.depth = depth;
+        self.seldepth = 0;
+    }
+
+    pub fn set_node_limit(&mut self, limit: u64) {
+        self.node_limit = limit;
+    }
+
+    pub fn out_of_nodes(&self) -> bool {
+        self.nodes >= self.node_limit
+    }
+
+    /// True every config::POLL_INTERVAL nodes
+    pub fn poll(&self) -> bool {
+        self.nodes.is_multiple_of(config::POLL_INTERVAL)
+    }
+
+    pub fn node(&mut self, ply: usize) {
         self.nodes += 1;
+        self.seldepth = self.seldepth.max(ply);
     }
 
     pub fn tt_hit(&mut self) {
         self.tt_hits += 1;
     }
+
+    pub fn probcut(&mut self) {
+        self.probcuts += 1;
+    }
+
+    pub fn iir(&mut self) {
+        self.internal_reductions += 1;
+    }
 }
 
-pub struct Stop(Arc<AtomicBool>);
+/// Shared by all search threads, the runtime can stop the search at any time
+#[derive(Clone)]
+pub struct Stop {
+    done: Arc<AtomicBool>,
+    /// See Holding the Bestmove
+    hold: Arc<AtomicBool>,
+    time: Arc<TimeManager>,
+}
 
 impl Stop {
-    pub fn new() -> Self {
-        Stop(Arc::new(AtomicBool::new(false)))
+    pub fn new(time: TimeManager, hold: bool) -> Self {
+        Stop {
+            done: Arc::new(AtomicBool::new(false)),
+            hold: Arc::new(AtomicBool::new(hold)),
+            time: Arc::new(time),
+        }
+    }
+
+    /// The bestmove can be sent as soon as the search is done
+    pub fn release(&self) {
+        self.hold.store(false, atomic::Ordering::Relaxed);
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.hold.load(atomic::Ordering::Relaxed)
     }
 
-    pub fn stop_search(&mut self) {
-        self.0.store(true, atomic::Ordering::Relaxed);
+    /// Starts the clock & turns the ponder search into a normal search
+    pub fn ponder_hit(&self) {
+        self.time.start();
+        self.release();
     }
 
-    pub fn clone(&self) -> Self {
-        Stop(Arc::clone(&self.0))
+    pub fn stop_search(&self) {
+        self.done.store(true, atomic::Ordering::Relaxed);
     }
 
     pub fn is_done(&self) -> bool {
-        self.0.load(atomic::Ordering::Relaxed)
+        self.done.load(atomic::Ordering::Relaxed)
+    }
+
+    pub fn time(&self) -> &TimeManager {
+        &self.time
+    }
+
+    /// Called every few nodes, stops the search once the hard time bound is exceeded
+    pub fn poll(&self) -> bool {
+        if self.time.hard_exceeded() {
+            self.stop_search();
+        }
+        self.is_done()
     }
 }
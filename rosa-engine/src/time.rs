@@ -1,81 +1,257 @@
-use core::panic;
-use std::time::Duration;
+//! # Go Command
+//! Parses the time control & the search limits of the uci go command.
+//! Without a clock (wtime/btime or movetime) the search runs until it is stopped or a limit is reached.
+//! Unknown tokens are ignored.
+//! ## Time Management
+//! Every search gets two bounds:
+//! - The soft bound is checked after every iteration (by the main thread).
+//!   It is scaled up if the best move keeps changing or the score drops
+//!   and scaled down if the best move has been stable for a few iterations.
+//!   A new iteration is only started if it is expected to finish before the hard bound.
+//! - The hard bound is polled during the search every few nodes & aborts the search.
+//!
+//! The `Move Overhead` is subtracted from the clock first, so GUI & network lag dont flag us.
+//! While pondering the clock only starts at ponderhit.
+//! ## Infinite & Ponder
+//! `go infinite` & `go ponder` must not send a bestmove before `stop` (or `ponderhit`),
+//! even if the search finished on its own (depth limit, mate found).
+
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
 use rosa_lib::piece::Clr;
 
-fn time(wtime: u64, btime: u64, winc: u64, binc: u64, clr: Clr) -> Duration {
-    let time = match clr {
-        Clr::White => (wtime / 20) + (winc / 2),
-        Clr::Black => (btime / 20) + (binc / 2),
-    };
+use crate::config;
+
+/// Sudden death is treated as this many moves to go
+const DEFAULT_MOVES_TO_GO: u64 = 30;
+const MAX_MOVES_TO_GO: u64 = 50;
+/// The hard bound is at most this many times the soft bound
+const HARD_FACTOR: u32 = 4;
+/// Never use more than 4/5 of the remaining time on one move
+const MAX_USAGE: (u32, u32) = (4, 5);
+/// Index: iterations the best move stayed the same
+const STABILITY_SCALE: [f64; 5] = [1.6, 1.25, 1.0, 0.85, 0.7];
+/// A score drop of this many centipawns doubles the soft bound
+const SCORE_DROP_DOUBLE: f64 = 200.0;
+/// The next iteration takes roughly this many times as long as the last one
+const ITERATION_GROWTH: u32 = 2;
+
+static MOVE_OVERHEAD: AtomicU64 = AtomicU64::new(config::DEFAULT_MOVE_OVERHEAD);
+
+pub fn set_move_overhead(millis: u64) {
+    let millis = millis.clamp(config::MIN_MOVE_OVERHEAD, config::MAX_MOVE_OVERHEAD);
+    MOVE_OVERHEAD.store(millis, Ordering::Relaxed);
+}
+
+pub struct TimeManager {
+    soft: Duration,
+    hard: Duration,
+    /// Unset while pondering (or if the search is untimed)
+    start: OnceLock<Instant>,
+}
+
+impl TimeManager {
+    /// clock & inc of the side to move, all in millis
+    pub fn new(clock: u64, inc: u64, movestogo: Option<u64>) -> TimeManager {
+        let overhead = MOVE_OVERHEAD.load(Ordering::Relaxed);
+        let remaining = Duration::from_millis(clock.saturating_sub(overhead));
+        let moves = movestogo
+            .unwrap_or(DEFAULT_MOVES_TO_GO)
+            .clamp(1, MAX_MOVES_TO_GO);
+
+        let max = remaining * MAX_USAGE.0 / MAX_USAGE.1;
+        // We only get the increment if we make it to the next move
+        let soft = remaining / moves as u32 + Duration::from_millis(inc) * 3 / 4;
+        let hard = (soft * HARD_FACTOR).min(max);
+        TimeManager {
+            soft: soft.min(hard),
+            hard,
+            start: OnceLock::new(),
+        }
+    }
+
+    /// go movetime: Exactly this long, no matter how the search goes
+    pub fn fixed(movetime: u64) -> TimeManager {
+        let overhead = MOVE_OVERHEAD.load(Ordering::Relaxed);
+        let time = Duration::from_millis(movetime.saturating_sub(overhead));
+        TimeManager {
+            soft: time,
+            hard: time,
+            start: OnceLock::new(),
+        }
+    }
+
+    pub fn infinite() -> TimeManager {
+        TimeManager {
+            soft: Duration::MAX,
+            hard: Duration::MAX,
+            start: OnceLock::new(),
+        }
+    }
+
+    /// Starts the clock, only the first call has an effect
+    pub fn start(&self) {
+        self.start.get_or_init(Instant::now);
+    }
+
+    /// None while the clock is not running
+    pub fn elapsed(&self) -> Option<Duration> {
+        self.start.get().map(|start| start.elapsed())
+    }
+
+    /// Time until the hard bound
+    pub fn remaining(&self) -> Duration {
+        match self.elapsed() {
+            Some(elapsed) => self.hard.saturating_sub(elapsed),
+            None => Duration::MAX,
+        }
+    }
+
+    pub fn hard_exceeded(&self) -> bool {
+        self.elapsed().is_some_and(|elapsed| elapsed >= self.hard)
+    }
+
+    /// Called by the main thread after every iteration
+    /// stability: iterations the best move did not change
+    /// score_drop: centipawns the score dropped compared to the last iteration
+    /// last_iteration: how long the last iteration took
+    pub fn stop_iterating(
+        &self, stability: usize, score_drop: i32, last_iteration: Duration,
+    ) -> bool {
+        let Some(elapsed) = self.elapsed() else {
+            return false;
+        };
+        let mut scale = STABILITY_SCALE[stability.min(STABILITY_SCALE.len() - 1)];
+        scale *= 1.0 + score_drop.max(0) as f64 / SCORE_DROP_DOUBLE;
+        // Infinite searches would overflow
+        let soft = Duration::try_from_secs_f64(self.soft.as_secs_f64() * scale)
+            .map_or(self.hard, |soft| soft.min(self.hard));
 
-    Duration::from_millis(time)
+        elapsed >= soft || elapsed + last_iteration * ITERATION_GROWTH >= self.hard
+    }
 }
 
 pub enum StartSearch {
     Untimed,
-    Timed(std::time::Duration),
-    Ponder(std::time::Duration),
+    Timed(TimeManager),
+    /// The clock starts at ponderhit
+    Ponder(TimeManager),
 }
 
-pub fn parse_time_from_go(cmd: Vec<&str>, clr: Clr) -> StartSearch {
-    let mut wtime = 0;
-    let mut btime = 0;
-    let mut winc = 0;
-    let mut binc = 0;
+/// Limits that end the search independent of the clock
+#[derive(Clone, Default, Debug)]
+pub struct SearchLimits {
+    pub depth: Option<u8>,
+    /// Nodes searched by the main thread
+    pub nodes: Option<u64>,
+    /// Stop once a mate in this many moves was found
+    pub mate: Option<i32>,
+    /// Only search these root moves (uci notation), empty if all moves are allowed
+    pub searchmoves: Vec<String>,
+    /// Hold the bestmove back until stop or ponderhit (see Infinite & Ponder)
+    pub infinite: bool,
+}
 
-    let mut ponder = false;
+impl SearchLimits {
+    pub fn depth_reached(&self, depth: u8) -> bool {
+        self.depth.is_some_and(|max| depth >= max)
+    }
 
-    if cmd.len() < 2 {
-        return StartSearch::Untimed;
+    pub fn mate_found(&self, mate_moves: Option<i32>) -> bool {
+        match (self.mate, mate_moves) {
+            (Some(max), Some(moves)) => moves > 0 && moves <= max,
+            _ => false,
+        }
     }
+}
 
-    // Skip "go"
-    let mut i = 1;
+/// The value after the token at i
+fn value<T: FromStr>(cmd: &[&str], i: usize) -> Option<T> {
+    cmd.get(i + 1).and_then(|val| val.parse().ok())
+}
 
-    while i < cmd.len() {
-        match cmd[i] {
-            "wtime" => {
-                i += 1;
-                wtime = cmd[i].parse().unwrap_or(0);
-            }
-            "btime" => {
-                i += 1;
-                btime = cmd[i].parse().unwrap_or(0);
-            }
-            "winc" => {
-                i += 1;
-                winc = cmd[i].parse().unwrap_or(0);
-            }
-            "binc" => {
-                i += 1;
-                binc = cmd[i].parse().unwrap_or(0);
-            }
-            "ponder" => {
-                ponder = true;
-            }
-            "movetime" => {
-                let movetime = cmd[i + 1].parse().unwrap_or(0);
-                return StartSearch::Timed(Duration::from_millis(movetime));
-            }
-            "infinite" => return StartSearch::Untimed,
+/// The parsed go command, the clock of the side to move is only picked once the search starts
+#[derive(Clone, Default, Debug)]
+pub struct Go {
+    wtime: Option<u64>,
+    btime: Option<u64>,
+    winc: u64,
+    binc: u64,
+    movestogo: Option<u64>,
+    movetime: Option<u64>,
+    infinite: bool,
+    ponder: bool,
+    limits: SearchLimits,
+}
 
-            // Ignore for now
-            "movestogo" => i += 1,
-            "mate" => i += 1,
-            "depth" => i += 1,
-            "nodes" => i += 1,
+impl Go {
+    /// cmd starts with "go"
+    pub fn parse(cmd: &[&str]) -> Go {
+        let mut go = Go::default();
+        // Skip "go"
+        let mut i = 1;
 
-            _ => {
-                panic!("Unknown go command part: {}", cmd[i])
+        while i < cmd.len() {
+            match cmd[i] {
+                "wtime" => go.wtime = value(cmd, i),
+                "btime" => go.btime = value(cmd, i),
+                "winc" => go.winc = value(cmd, i).unwrap_or(0),
+                "binc" => go.binc = value(cmd, i).unwrap_or(0),
+                "movestogo" => go.movestogo = value(cmd, i),
+                "movetime" => go.movetime = value(cmd, i),
+                "depth" => go.limits.depth = value(cmd, i),
+                "nodes" => go.limits.nodes = value(cmd, i),
+                "mate" => go.limits.mate = value(cmd, i),
+                "ponder" => go.ponder = true,
+                "infinite" => go.infinite = true,
+                "searchmoves" => {
+                    // Takes every following token that looks like a move
+                    while let Some(mv) = cmd.get(i + 1).filter(|mv| is_mv(mv)) {
+                        go.limits.searchmoves.push(mv.to_string());
+                        i += 1;
+                    }
+                }
+                _ => {}
             }
+            i += 1;
         }
-        i += 1;
+        go
     }
 
-    if !ponder {
-        StartSearch::Timed(time(wtime, btime, winc, binc, clr))
-    } else {
-        StartSearch::Ponder(time(wtime, btime, winc, binc, clr))
+    /// clr: The side to move
+    pub fn start(self, clr: Clr) -> (StartSearch, SearchLimits) {
+        let (clock, inc) = match clr {
+            Clr::White => (self.wtime, self.winc),
+            Clr::Black => (self.btime, self.binc),
+        };
+        let time = match (self.movetime, clock) {
+            _ if self.infinite && !self.ponder => None,
+            (Some(movetime), _) => Some(TimeManager::fixed(movetime)),
+            (None, Some(clock)) => Some(TimeManager::new(clock, inc, self.movestogo)),
+            (None, None) => None,
+        };
+        // The clock of a ponder search only starts at ponderhit
+        let start = match time {
+            _ if self.ponder => StartSearch::Ponder(time.unwrap_or_else(TimeManager::infinite)),
+            Some(time) => StartSearch::Timed(time),
+            None => StartSearch::Untimed,
+        };
+        let mut limits = self.limits;
+        limits.infinite = self.infinite || self.ponder;
+        (start, limits)
     }
 }
+
+pub fn parse_time_from_go(cmd: Vec<&str>, clr: Clr) -> (StartSearch, SearchLimits) {
+    Go::parse(&cmd).start(clr)
+}
+
+/// e2e4 or e7e8q
+fn is_mv(token: &str) -> bool {
+    let b = token.as_bytes();
+    let sq = |f: u8, r: u8| (b'a'..=b'h').contains(&f) && (b'1'..=b'8').contains(&r);
+    (b.len() == 4 || b.len() == 5) && sq(b[0], b[1]) && sq(b[2], b[3])
+}
@@ -0,0 +1,144 @@
+//! # UCI Commands
+//! Every input line is parsed into a UciCommand before the runtime acts on it.
+//! Invalid commands are reported as `info string` & otherwise ignored,
+//! a gui (or a tournament manager) can never crash the engine.
+//! ## Debug Commands
+//! Besides the uci protocol a few commands only exist for debugging (see UciCommand)
+
+use crate::time::Go;
+
+pub enum UciCommand {
+    Uci,
+    /// debug on|off
+    Debug(bool),
+    IsReady,
+    SetOption {
+        name: String,
+        value: Option<String>,
+    },
+    /// Rosa does not need a registration, the command is only accepted
+    Register,
+    UciNewGame,
+    /// fen: None for the starting position
+    Position {
+        fen: Option<Vec<String>>,
+        moves: Vec<String>,
+    },
+    Go(Go),
+    Stop,
+    PonderHit,
+    Quit,
+
+    // Debug Commands
+    /// Play a single legal move
+    Move(String),
+    /// Division search at this depth (at least 1)
+    Div(u8),
+    Print,
+    /// The search parameters as JSON (see params)
+    Params,
+    Key,
+    Magics,
+    Eval,
+    Color,
+    /// tt save|load <file>
+    Tt(TtAction, String),
+}
+
+pub enum TtAction {
+    Save,
+    Load,
+}
+
+impl UciCommand {
+    /// The error is the message for the gui
+    pub fn parse(line: &str) -> Result<UciCommand, String> {
+        let parts: Vec<&str> = line.split_ascii_whitespace().collect();
+        let Some(name) = parts.first() else {
+            return Err("empty command".to_string());
+        };
+
+        let cmd = match name.to_lowercase().as_str() {
+            "uci" => UciCommand::Uci,
+            "debug" => match parts.get(1) {
+                Some(&"on") => UciCommand::Debug(true),
+                Some(&"off") => UciCommand::Debug(false),
+                _ => return Err("usage: debug on|off".to_string()),
+            },
+            "isready" => UciCommand::IsReady,
+            "setoption" => {
+                let Some((name, value)) = parse_setoption(&parts) else {
+                    return Err("usage: setoption name <id> [value <x>]".to_string());
+                };
+                UciCommand::SetOption { name, value }
+            }
+            "register" => UciCommand::Register,
+            "ucinewgame" => UciCommand::UciNewGame,
+            "position" => parse_position(&parts)?,
+            "go" => UciCommand::Go(Go::parse(&parts)),
+            "stop" => UciCommand::Stop,
+            "ponderhit" => UciCommand::PonderHit,
+            "quit" => UciCommand::Quit,
+
+            "moves" => match parts.get(1) {
+                Some(mv) => UciCommand::Move(mv.to_string()),
+                None => return Err("usage: moves <move>".to_string()),
+            },
+            "div" => match parts.get(1).and_then(|depth| depth.parse().ok()) {
+                Some(depth) if depth > 0 => UciCommand::Div(depth),
+                _ => return Err("usage: div <depth> (at least 1)".to_string()),
+            },
+            "print" | "p" | "d" => UciCommand::Print,
+            "params" => UciCommand::Params,
+            "key" => UciCommand::Key,
+            "magics" => UciCommand::Magics,
+            "eval" => UciCommand::Eval,
+            "color" => UciCommand::Color,
+            "tt" => {
+                let action = match parts.get(1) {
+                    Some(&"save") => TtAction::Save,
+                    Some(&"load") => TtAction::Load,
+                    _ => return Err("usage: tt save|load <file>".to_string()),
+                };
+                if parts.len() < 3 {
+                    return Err("usage: tt save|load <file>".to_string());
+                }
+                UciCommand::Tt(action, parts[2..].join(" "))
+            }
+            _ => return Err(format!("unknown command {name}")),
+        };
+        Ok(cmd)
+    }
+}
+
+/// position [fen <fenstring> | startpos] [moves <move1> ... <movei>]
+fn parse_position(parts: &[&str]) -> Result<UciCommand, String> {
+    let moves_pos = parts.iter().position(|part| *part == "moves");
+    let moves = match moves_pos {
+        Some(i) => parts[i + 1..].iter().map(|mv| mv.to_string()).collect(),
+        None => Vec::new(),
+    };
+    let fen_end = moves_pos.unwrap_or(parts.len());
+
+    let fen = match parts.get(1) {
+        Some(&"startpos") => None,
+        Some(&"fen") => Some(parts[2..fen_end].iter().map(|f| f.to_string()).collect()),
+        _ => return Err("usage: position [fen <fenstring> | startpos] moves ...".to_string()),
+    };
+    Ok(UciCommand::Position { fen, moves })
+}
+
+/// setoption name <id> [value <x>]
+/// Both the name & the value can contain spaces
+fn parse_setoption(parts: &[&str]) -> Option<(String, Option<String>)> {
+    let name_start = parts.iter().position(|part| *part == "name")? + 1;
+    let value_pos = parts.iter().position(|part| *part == "value");
+    let name_end = value_pos.unwrap_or(parts.len());
+    if name_start >= name_end {
+        return None;
+    }
+
+    let name = parts[name_start..name_end].join(" ");
+    let value = value_pos.map(|i| parts[i + 1..].join(" "));
+    Some((name, value))
+}
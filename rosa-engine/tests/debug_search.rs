@@ -4,17 +4,20 @@ use rosa_engine::make;
 use rosa_engine::make::Legal;
 use rosa_engine::mv;
 use rosa_engine::mv::mv_gen;
-use rosa_engine::mv::mv_gen::MvGenStage;
-use rosa_engine::search::TT;
 
 use rosa_lib::mv::Mv;
 use rosa_lib::pos;
-use rosa_lib::tt;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
 
 /// Only turn this on if there is an active error
 /// Slows down the tests like crazy
 const BE_VERY_THOROUGH: bool = false;
 
+// Node counts per (key, depth); the engine TT cant hold the counts
+thread_local! { static COUNTS: RefCell<HashMap<(u64, u8), u64>> = RefCell::new(HashMap::new())}
+
 pub fn debug_search(p: &mut pos::Pos, depth: u8) -> u64 {
     if BE_VERY_THOROUGH {
         thorough_search(p, depth, &mut Vec::new())
@@ -32,14 +35,8 @@ fn not_so_thorough_search(p: &mut pos::Pos, depth: u8) -> u64 {
         return 1;
     }
 
-    let entry = TT.get(p.key());
-
-    if let Some(e) = entry
-        && e.key == p.key()
-        && e.depth == depth
-    {
-        // We found a valid entry
-        return e.score as u64;
+    if let Some(count) = COUNTS.with(|c| c.borrow().get(&(p.key().val(), depth)).copied()) {
+        return count;
     }
 
     // Null Move
@@ -74,13 +71,7 @@ fn not_so_thorough_search(p: &mut pos::Pos, depth: u8) -> u64 {
         }
     }
 
-    TT.set(tt::Entry {
-        key: (p.key()),
-        score: (count as i32),
-        mv: (Mv::null()),
-        depth: (depth),
-        node_type: (tt::EntryType::Exact),
-    });
+    COUNTS.with(|c| c.borrow_mut().insert((p.key().val(), depth), count));
 
     count
 }
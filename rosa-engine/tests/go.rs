@@ -0,0 +1,37 @@
+use rosa_engine::time::{self, StartSearch, TimeManager};
+use rosa_lib::piece::Clr;
+use std::time::Duration;
+
+#[test]
+fn parse_limits() {
+    let cmd = "go depth 7 nodes 5000 mate 3 searchmoves e2e4 d2d4 unknown movestogo";
+    let (start, limits) = time::parse_time_from_go(cmd.split(' ').collect(), Clr::White);
+    assert!(matches!(start, StartSearch::Untimed));
+    assert_eq!(limits.depth, Some(7));
+    assert_eq!(limits.nodes, Some(5000));
+    assert_eq!(limits.mate, Some(3));
+    assert_eq!(limits.searchmoves, vec!["e2e4", "d2d4"]);
+
+    let cmd = "go wtime 1000 btime 2000 winc 100";
+    let (start, _) = time::parse_time_from_go(cmd.split(' ').collect(), Clr::Black);
+    assert!(matches!(start, StartSearch::Timed(_)));
+}
+
+#[test]
+fn time_bounds() {
+    let sudden_death = TimeManager::new(10_000, 0, None);
+    let last_move = TimeManager::new(10_000, 0, Some(1));
+    // The clock is not running yet (pondering)
+    assert_eq!(last_move.remaining(), Duration::MAX);
+    assert!(!last_move.stop_iterating(0, 0, Duration::ZERO));
+
+    sudden_death.start();
+    last_move.start();
+    assert!(last_move.remaining() > sudden_death.remaining());
+    assert!(last_move.remaining() <= Duration::from_millis(8000));
+
+    let flagging = TimeManager::new(10, 0, None);
+    flagging.start();
+    assert!(flagging.hard_exceeded());
+    assert!(flagging.stop_iterating(5, 0, Duration::ZERO));
+}
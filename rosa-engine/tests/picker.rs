@@ -0,0 +1,153 @@
+use rosa_engine::fen;
+use rosa_engine::mv::mv_gen;
+use rosa_engine::mv::picker::MovePicker;
+use rosa_engine::mv::see;
+use rosa_engine::runtime;
+
+use rosa_lib::history;
+use rosa_lib::mv::Mv;
+use rosa_lib::piece::ClrPiece;
+use rosa_lib::pos;
+
+fn pos(fen: &str) -> pos::Pos {
+    runtime::init();
+    fen::fen(fen.split_ascii_whitespace().collect(), Vec::new())
+}
+
+/// The picker has to return every generated move exactly once
+fn check_complete(p: &pos::Pos, tt_mv: Option<Mv>, killers: [Mv; 2], counter: Mv) {
+    let mut generated: Vec<String> = mv_gen::gen_mvs(p)
+        .into_iter()
+        .map(|m| m.to_string())
+        .collect();
+    let mut picked = Vec::new();
+    let mut picker = MovePicker::new(tt_mv, killers, counter, [None; 2]);
+    while let Some(mv) = picker.next(p) {
+        picked.push(mv.to_string());
+    }
+    generated.sort();
+    picked.sort();
+    assert_eq!(generated, picked);
+}
+
+#[test]
+fn picker_complete() {
+    let p = pos("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+    check_complete(&p, None, [Mv::null(); 2], Mv::null());
+
+    let tt_mv = Mv::new_from_str("e5f7", &p);
+    let killer = Mv::new_from_str("a2a3", &p);
+    let counter = Mv::new_from_str("e1g1", &p);
+    check_complete(&p, Some(tt_mv), [killer, killer], counter);
+}
+
+#[test]
+fn picker_invalid_hints() {
+    let p = pos("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1");
+    // Both are moves of a different position
+    let other = pos("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+    let tt_mv = Mv::new_from_str("e5f7", &other);
+    let killer = Mv::new_from_str("e1g1", &other);
+    check_complete(&p, Some(tt_mv), [killer, Mv::null()], killer);
+}
+
+#[test]
+fn picker_tt_first() {
+    let p = pos("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+    let tt_mv = Mv::new_from_str("a2a3", &p);
+    let mut picker = MovePicker::new(Some(tt_mv), [Mv::null(); 2], Mv::null(), [None; 2]);
+    assert!(picker.next(&p).unwrap().fuzzy_compare(&tt_mv));
+}
+
+/// Good captures, then the killers & the counter move, then the other quiets by history
+#[test]
+fn picker_killers_after_captures() {
+    let p = pos("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+    let killers = [Mv::new_from_str("a2a3", &p), Mv::new_from_str("g2g3", &p)];
+    let counter = Mv::new_from_str("e1g1", &p);
+    // A quiet with a better history still comes after them
+    history::clear();
+    let good_quiet = Mv::new_from_str("d5d6", &p);
+    history::update_quiet(&good_quiet, ClrPiece::WPawn, &[None; 2], history::bonus(10));
+
+    let mut picker = MovePicker::new(None, killers, counter, [None; 2]);
+    let mut picked = Vec::new();
+    while let Some(mv) = picker.next(&p) {
+        picked.push(mv);
+    }
+    let first_quiet = picked.iter().position(|mv| !mv.is_cap()).unwrap();
+    assert!(first_quiet > 0);
+    let hints: Vec<String> = picked[first_quiet..first_quiet + 3]
+        .iter()
+        .map(|mv| mv.to_string())
+        .collect();
+    assert_eq!(hints, vec!["a2a3", "g2g3", "e1g1"]);
+    assert_eq!(picked[first_quiet + 3].to_string(), "d5d6");
+}
+
+#[test]
+fn see_defended_pawn() {
+    // Rook takes a pawn that is defended by a pawn
+    let p = pos("4k3/8/2p5/3p4/8/8/3R4/4K3 w - - 0 1");
+    let mv = Mv::new_from_str("d2d5", &p);
+    assert_eq!(see::see(&p, &mv), 100 - 500);
+}
+
+#[test]
+fn see_xray() {
+    // Both sides have a second rook behind the first one
+    let p = pos("3rk3/3r4/8/3p4/8/8/3R4/3RK3 w - - 0 1");
+    let mv = Mv::new_from_str("d2d5", &p);
+    assert_eq!(see::see(&p, &mv), 100 - 500);
+
+    let p = pos("3rk3/8/8/3p4/8/8/3R4/3RK3 w - - 0 1");
+    let mv = Mv::new_from_str("d2d5", &p);
+    assert_eq!(see::see(&p, &mv), 100);
+}
+
+#[test]
+fn see_undefended() {
+    let p = pos("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1");
+    let mv = Mv::new_from_str("d1d5", &p);
+    assert_eq!(see::see(&p, &mv), 900);
+}
+
+/// The tt only saves the compact move, it has to restore the exact generated move
+#[test]
+fn compact_round_trip() {
+    for fen in [
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+        "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+    ] {
+        let p = pos(fen);
+        for mv in mv_gen::gen_mvs(&p) {
+            let restored = Mv::from_compact(mv.compact(), &p);
+            assert!(restored.fuzzy_compare(&mv), "{mv}");
+            if mv.is_cap() {
+                assert_eq!(restored.cap_victim(), mv.cap_victim());
+            }
+        }
+    }
+}
+
+/// A tt entry of another position (key collision) must not turn into a king capture
+#[test]
+fn compact_king_victim() {
+    let p = pos("4k3/8/8/8/8/8/8/R3K3 w - - 0 1");
+    let mv = Mv::new_from_str("a1a8", &p);
+    let other = pos("k7/8/8/8/8/8/8/R3K3 w - - 0 1");
+    assert!(Mv::from_compact(mv.compact(), &other).is_null());
+}
+
+#[test]
+fn noisy_skips_bad_captures() {
+    // Rxd5 loses the rook, Rxa7 is free
+    let p = pos("4k3/r7/2p5/3p4/8/8/R2R4/4K3 w - - 0 1");
+    let mut picker = MovePicker::new_noisy(None, false);
+    let mut picked = Vec::new();
+    while let Some(mv) = picker.next(&p) {
+        picked.push(mv.to_string());
+    }
+    assert_eq!(picked, vec!["a2a7"]);
+}
@@ -0,0 +1,37 @@
+use rosa_engine::fen;
+use rosa_engine::quiscence::quiscence_search;
+use rosa_engine::runtime;
+use rosa_engine::thread_search::SearchStats;
+
+use rosa_lib::score::Score;
+
+fn qsearch(fen: &str, checks: bool) -> Score {
+    runtime::init();
+    let mut p = fen::fen(fen.split_ascii_whitespace().collect(), Vec::new());
+    let mut stats = SearchStats::new(0);
+    quiscence_search(
+        &mut p,
+        -Score::INFINITY,
+        Score::INFINITY,
+        0,
+        checks,
+        &mut stats,
+    )
+}
+
+/// In check there is no stand pat
+#[test]
+fn mated_at_horizon() {
+    let score = qsearch(
+        "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3",
+        false,
+    );
+    assert!(score == Score::mated_in(0));
+}
+
+/// The only way to win the queen is a quiet check (Nc7+ forks king & queen)
+#[test]
+fn quiet_check_fork() {
+    let fen = "q3k3/8/N7/8/8/8/8/6K1 w - - 0 1";
+    assert!(qsearch(fen, true) > qsearch(fen, false));
+}
@@ -0,0 +1,55 @@
+use rosa_engine::cuckoo;
+use rosa_engine::fen;
+use rosa_engine::runtime;
+use rosa_engine::stack::Stack;
+
+use rosa_lib::mv::Mv;
+use rosa_lib::pos;
+use rosa_lib::score::Score;
+
+fn pos(moves: &str) -> pos::Pos {
+    runtime::init();
+    fen::starting_pos(moves.split_ascii_whitespace().collect())
+}
+
+#[test]
+fn threefold() {
+    let p = pos("g1f3 g8f6 f3g1 f6g8");
+    // Before the root the position has to repeat twice
+    assert!(!p.is_repetition(0));
+    assert!(p.is_repetition(4));
+
+    let p = pos("g1f3 g8f6 f3g1 f6g8 g1f3 g8f6 f3g1 f6g8");
+    assert!(p.is_repetition(0));
+}
+
+#[test]
+fn irreversible_moves() {
+    // The pawn move resets the history, so the knights cant repeat anything before it
+    let p = pos("g1f3 g8f6 f3g1 f6g8 e2e4 e7e5 g1f3 g8f6 f3g1 f6g8");
+    assert!(!p.is_repetition(0));
+    assert_eq!(p.reversible_plies(), 4);
+}
+
+#[test]
+fn upcoming() {
+    // Black can go back to the starting position with f6g8
+    let p = pos("g1f3 g8f6 f3g1");
+    assert!(cuckoo::upcoming_repetition(&p, 4));
+    // The starting position is not part of the search tree
+    assert!(!cuckoo::upcoming_repetition(&p, 3));
+
+    // Only white could undo its last move
+    let p = pos("g1f3 b8c6 f3g1 c6b8 b1c3");
+    assert!(!cuckoo::upcoming_repetition(&p, 10));
+}
+
+/// The root side loses the contempt, the opponent gains it
+#[test]
+fn contempt_draw_score() {
+    let mut stack = Stack::new();
+    stack.set_contempt(20);
+    assert_eq!(stack.draw_score(), Score::cp(-20));
+    stack.push(Mv::null(), None);
+    assert_eq!(stack.draw_score(), Score::cp(20));
+}
@@ -0,0 +1,140 @@
+use rosa_engine::config;
+use rosa_engine::fen;
+use rosa_engine::runtime;
+use rosa_engine::search;
+use rosa_engine::stack::Stack;
+use rosa_engine::thread_search::{MAIN_THREAD, Stop, ThreadReport};
+use rosa_engine::time::{SearchLimits, TimeManager};
+
+use rosa_lib::mv::Mv;
+
+use std::sync::mpsc;
+use std::thread;
+
+/// Searches the position on a single thread to the depth
+/// Returns the report of the last iteration
+fn search(fen: &str, depth: u8) -> ThreadReport {
+    runtime::init();
+    let p = fen::try_fen(fen.split(' ').collect(), Vec::new()).unwrap();
+    let limits = SearchLimits {
+        depth: Some(depth),
+        ..Default::default()
+    };
+    let stop = Stop::new(TimeManager::infinite(), false);
+    let (tx, rx) = mpsc::channel();
+    thread::Builder::new()
+        .stack_size(16 * config::MB as usize)
+        .spawn(move || search::search(p, tx, stop, MAIN_THREAD, 1, limits))
+        .unwrap();
+    rx.iter().last().unwrap()
+}
+
+fn best_mv(report: &ThreadReport) -> String {
+    report.pv()[0].to_string()
+}
+
+/// Fine #70: Only Kb1 wins, the black king has to run out of moves.
+/// A null move in a position with only pawns (or a double null move) would skip the zugzwang
+#[test]
+fn pawn_zugzwang() {
+    let report = search("8/k7/3p4/p2P1p2/P2P1P2/8/8/K7 w - - 0 1", 12);
+    assert_eq!(best_mv(&report), "a1b1");
+}
+
+/// WAC.004: Qxh7+ Kxh7 hxg6#, without the check extension the mate is only found at depth 5
+#[test]
+fn check_extension() {
+    let report = search(
+        "r1bq2rk/pp3pbp/2p1p1pQ/7P/3P4/2PB1N2/PP3PPR/2KR4 w - - 0 1",
+        4,
+    );
+    assert_eq!(best_mv(&report), "h6h7");
+    assert_eq!(report.score().mate_moves(), Some(2));
+}
+
+#[test]
+fn extension_budget() {
+    let mut stack = Stack::new();
+    stack.push(Mv::null(), None);
+    assert!(stack.extend(2));
+    stack.push(Mv::null(), None);
+    assert!(stack.extend(2));
+    assert_eq!(stack.extensions(), 2);
+    // The path used up the budget
+    stack.push(Mv::null(), None);
+    assert!(!stack.extend(2));
+    assert_eq!(stack.extensions(), 2);
+    // A sibling path only counts its own extensions
+    stack.pop();
+    stack.pop();
+    stack.push(Mv::null(), None);
+    assert_eq!(stack.extensions(), 1);
+}
+
+#[test]
+fn lmr_table() {
+    runtime::init();
+    // The first move & depth 1 are never reduced
+    assert_eq!(search::lmr_table(1, 30), 0);
+    assert_eq!(search::lmr_table(30, 1), 0);
+    for depth in 1..40 {
+        for mv_count in 1..40 {
+            let r = search::lmr_table(depth, mv_count);
+            assert!(search::lmr_table(depth + 1, mv_count) >= r);
+            assert!(search::lmr_table(depth, mv_count + 1) >= r);
+        }
+    }
+    assert!(search::lmr_table(20, 30) > search::lmr_table(4, 4));
+}
+
+#[test]
+fn lmr_adjustments() {
+    runtime::init();
+    let base = search::lmr_reduction(12, 20, 0, &[false; 5]);
+    assert_eq!(base, search::lmr_table(12, 20));
+    // pv node, improving, killer, check, capture
+    for i in 0..5 {
+        let mut less = [false; 5];
+        less[i] = true;
+        assert_eq!(search::lmr_reduction(12, 20, 0, &less), base - 1);
+    }
+    assert_eq!(search::lmr_reduction(12, 20, 0, &[true; 5]), base - 5);
+    // Good history reduces less, bad history more
+    assert!(search::lmr_reduction(12, 20, 16384, &[false; 5]) < base);
+    assert!(search::lmr_reduction(12, 20, -16384, &[false; 5]) > base);
+}
+
+/// Both only start at a few plies of depth (see params)
+#[test]
+fn probcut_and_iir() {
+    let report = search(
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        8,
+    );
+    assert!(report.stats().probcuts > 0);
+    // The pv changes a few times & reaches nodes without a tt move
+    let report = search("2r3k1/pp3ppp/8/8/8/8/PP3PPP/2R3K1 w - - 0 1", 8);
+    assert!(report.stats().internal_reductions > 0);
+}
+
+/// Qd8+ Kxd8 Bg5+ Kc7 Bd8#: After the sacrifice white is a queen down,
+/// futility pruning would skip the quiet checks
+#[test]
+fn quiet_checks_not_pruned() {
+    let report = search(
+        "rnb1kb1r/pp3ppp/2p5/4q3/4n3/3Q4/PPPB1PPP/2KR1BNR w kq - 0 1",
+        5,
+    );
+    assert_eq!(best_mv(&report), "d3d8");
+    assert_eq!(report.score().mate_moves(), Some(3));
+}
+
+/// Qh5+ g6 Bxg6+ hxg6 Qxg6#: Pruning the nodes in check would miss the mate
+#[test]
+fn in_check_not_pruned() {
+    let report = search(
+        "r2qk2r/pb4pp/1n2Pb2/2B2Q2/p1p5/2P5/2B2PPP/RN2R1K1 w - - 1 1",
+        3,
+    );
+    assert!(report.score().is_mate());
+}
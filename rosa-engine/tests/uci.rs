@@ -0,0 +1,331 @@
+use rosa_engine::fen;
+use rosa_engine::runtime;
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// Long enough for a shallow search in debug builds
+const TIMEOUT: Duration = Duration::from_secs(20);
+/// Nothing should be printed in this time
+const QUIET: Duration = Duration::from_millis(300);
+
+/// Talks to the engine binary like a gui would
+struct Engine {
+    child: Child,
+    stdin: ChildStdin,
+    lines: mpsc::Receiver<String>,
+}
+
+impl Engine {
+    fn new() -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_rosa-engine"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let (tx, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut engine = Engine {
+            child,
+            stdin,
+            lines,
+        };
+        engine.send("isready");
+        engine.expect("readyok");
+        engine
+    }
+
+    fn send(&mut self, cmd: &str) {
+        writeln!(self.stdin, "{cmd}").unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    /// Skips lines until one starts with prefix
+    fn expect(&self, prefix: &str) -> String {
+        loop {
+            match self.lines.recv_timeout(TIMEOUT) {
+                Ok(line) if line.starts_with(prefix) => return line,
+                Ok(_) => {}
+                Err(e) => panic!("Expected {prefix}: {e}"),
+            }
+        }
+    }
+
+    fn expect_none(&self, prefix: &str) {
+        while let Ok(line) = self.lines.recv_timeout(QUIET) {
+            assert!(!line.starts_with(prefix), "Unexpected {line}");
+        }
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+    }
+}
+
+#[test]
+fn handshake() {
+    let mut engine = Engine::new();
+    engine.send("uci");
+    engine.expect("id name");
+    engine.expect("uciok");
+}
+
+#[test]
+fn ponderhit() {
+    let mut engine = Engine::new();
+    // The gui adds the expected reply to the position itself
+    engine.send("position startpos moves e2e4 e7e5");
+    engine.send("go ponder wtime 2000 btime 2000");
+    engine.expect_none("bestmove");
+    engine.send("ponderhit");
+    engine.expect("bestmove");
+}
+
+#[test]
+fn stop_ponder() {
+    let mut engine = Engine::new();
+    // Pondering does not need a previous search
+    engine.send("position startpos moves d2d4");
+    engine.send("go ponder wtime 60000 btime 60000");
+    engine.expect_none("bestmove");
+    engine.send("stop");
+    engine.expect("bestmove");
+    engine.send("isready");
+    engine.expect("readyok");
+}
+
+#[test]
+fn finished_ponder_waits() {
+    let mut engine = Engine::new();
+    engine.send("go ponder depth 1");
+    engine.expect("info depth 1");
+    engine.expect_none("bestmove");
+    engine.send("stop");
+    engine.expect("bestmove");
+}
+
+#[test]
+fn infinite() {
+    let mut engine = Engine::new();
+    engine.send("go infinite depth 1");
+    engine.expect_none("bestmove");
+    engine.send("stop");
+    engine.expect("bestmove");
+}
+
+#[test]
+fn out_of_order() {
+    let mut engine = Engine::new();
+    engine.send("ponderhit");
+    engine.send("stop");
+    engine.send("go depth 2");
+    engine.expect("bestmove");
+    engine.send("ponderhit");
+    engine.send("go infinite");
+    // A second go stops the first search
+    engine.send("go infinite");
+    engine.expect("bestmove");
+    engine.send("stop");
+    engine.expect("bestmove");
+    engine.send("stop");
+    engine.send("isready");
+    engine.expect("readyok");
+}
+
+#[test]
+fn commands_before_isready() {
+    let mut engine = Engine::new();
+    engine.send("ucinewgame");
+    engine.send("position startpos moves e2e4");
+    engine.send("go depth 1");
+    engine.expect("bestmove");
+}
+
+#[test]
+fn invalid_commands() {
+    let mut engine = Engine::new();
+    for cmd in [
+        "div",
+        "div 0",
+        "debug maybe",
+        "unknown",
+        "position",
+        "position fen 8/8 w - -",
+        "position fen rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNX w KQkq - 0 1",
+        "position startpos moves e2e5",
+        "moves a1a8",
+        "tt load",
+    ] {
+        engine.send(cmd);
+        engine.expect("info string");
+    }
+    engine.send("register later");
+    engine.send("isready");
+    engine.expect("readyok");
+}
+
+/// The search has to end on its own & only play one of the searchmoves
+#[test]
+fn depth_and_searchmoves() {
+    let mut engine = Engine::new();
+    engine.send("go depth 4 searchmoves a2a3");
+    assert!(engine.expect("bestmove").starts_with("bestmove a2a3"));
+}
+
+#[test]
+fn repeated_root() {
+    let mut engine = Engine::new();
+    // The starting position occurs for the third time, it still needs a searched move
+    engine.send("position startpos moves g1f3 g8f6 f3g1 f6g8 g1f3 g8f6 f3g1 f6g8");
+    engine.send("go depth 4");
+    engine.expect("info depth 4");
+    engine.expect("bestmove");
+}
+
+/// Black can repeat the position a third time with Ra7
+#[test]
+fn contempt() {
+    let position =
+        "position fen 4k3/r7/8/8/8/8/R7/4K3 w - - 0 1 moves a2b2 a7b7 b2a2 b7a7 a2b2 a7b7 b2a2";
+    let mut engine = Engine::new();
+    // A draw is worth more than the equal endgame
+    engine.send("setoption name Contempt value -100");
+    engine.send(position);
+    engine.send("go depth 6");
+    assert!(engine.expect("bestmove").starts_with("bestmove b7a7"));
+    // A draw is worth less
+    engine.send("ucinewgame");
+    engine.send("setoption name Contempt value 100");
+    engine.send(position);
+    engine.send("go depth 6");
+    assert!(!engine.expect("bestmove").starts_with("bestmove b7a7"));
+}
+
+#[test]
+fn fallback_bestmove() {
+    let mut engine = Engine::new();
+    // Checkmated, there is no move to search
+    engine.send("position fen 7k/6Q1/6K1/8/8/8/8/8 b - - 0 1");
+    engine.send("go depth 3");
+    assert_eq!(engine.expect("bestmove"), "bestmove 0000");
+    engine.send("go infinite");
+    engine.expect_none("bestmove");
+    engine.send("stop");
+    assert_eq!(engine.expect("bestmove"), "bestmove 0000");
+}
+
+#[test]
+fn debug() {
+    let mut engine = Engine::new();
+    engine.send("debug on");
+    engine.send("go depth 2");
+    // Rosa has no tablebases, the tt hits are only reported in debug mode
+    assert!(!engine.expect("info depth").contains("tbhits"));
+    assert!(engine.expect("info string").contains("tt hits"));
+    engine.expect("bestmove");
+}
+
+#[test]
+fn params() {
+    let mut engine = Engine::new();
+    engine.send("params");
+    let json = engine.expect("{");
+    assert!(json.ends_with('}'));
+    assert!(json.contains("\"lmr_moves\": 2"));
+    // Only tunable with the feature
+    engine.send("setoption name lmr_moves value 5");
+    engine.send("params");
+    let moves = if cfg!(feature = "tune") { 5 } else { 2 };
+    assert!(
+        engine
+            .expect("{")
+            .contains(&format!("\"lmr_moves\": {moves}"))
+    );
+}
+
+#[test]
+fn try_fen() {
+    runtime::init();
+    let start = fen::START_FEN.join(" ");
+    let fen_parts: Vec<&str> = start.split(' ').collect();
+    let p = fen::try_fen(fen_parts.clone(), vec!["e2e4", "e7e5"]).unwrap();
+    assert_eq!(p.key(), fen::starting_pos(vec!["e2e4", "e7e5"]).key());
+    // The halfmove clock & fullmove number are optional
+    assert!(fen::try_fen(fen_parts[..4].to_vec(), Vec::new()).is_ok());
+
+    assert!(fen::try_fen(fen_parts.clone(), vec!["e2e5"]).is_err());
+    // No castling without the rook
+    let no_rook = "rnbqkbn1/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+    assert!(fen::try_fen(no_rook.split(' ').collect(), Vec::new()).is_err());
+    // White just moved & left its king in check
+    let wrong_side = "4k3/8/8/8/8/8/8/r3K3 b - - 0 1";
+    assert!(fen::try_fen(wrong_side.split(' ').collect(), Vec::new()).is_err());
+}
+
+#[test]
+fn quit() {
+    let mut engine = Engine::new();
+    engine.send("go infinite");
+    engine.send("quit");
+    assert!(engine.child.wait().unwrap().success());
+}
+
+/// Every parameter at the ends of its range has to search without panicking
+#[cfg(feature = "tune")]
+#[test]
+fn param_ranges() {
+    use rosa_engine::params;
+
+    let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+    for use_max in [false, true] {
+        let mut engine = Engine::new();
+        for (name, min, max) in params::ranges() {
+            let value = if use_max { max } else { min };
+            engine.send(&format!("setoption name {name} value {value}"));
+        }
+        for position in ["position startpos", &format!("position fen {kiwipete}")] {
+            engine.send(position);
+            engine.send("go depth 5");
+            engine.expect("bestmove");
+        }
+        engine.send("isready");
+        engine.expect("readyok");
+    }
+}
+
+#[test]
+fn tt_file_matches_hash() {
+    let path = std::env::temp_dir().join(format!("rosa_tt_{}.bin", std::process::id()));
+    let path = path.to_str().unwrap();
+    let mut engine = Engine::new();
+    engine.send("setoption name Hash value 1");
+    engine.send("position startpos");
+    engine.send("go depth 4");
+    engine.expect("bestmove");
+    engine.send(&format!("tt save {path}"));
+    assert!(engine.expect("info string").ends_with("done"));
+
+    // The table keeps the Hash size, so the file does not fit anymore
+    engine.send("setoption name Hash value 2");
+    engine.send(&format!("tt load {path}"));
+    assert!(engine.expect("info string").contains("failed"));
+
+    engine.send("setoption name Hash value 1");
+    engine.send(&format!("tt load {path}"));
+    assert!(engine.expect("info string").ends_with("done"));
+    std::fs::remove_file(path).unwrap();
+}
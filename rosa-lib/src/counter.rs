@@ -0,0 +1,36 @@
+//! ## Counter Move Heuristic
+//! Many moves have a natural reply, independent of where in the tree they are played.
+//! The counter move table saves the last quiet move that produced a beta cutoff as a response to the previous move.
+//! The data is saved in a global (thread local) table, indexed by: piece of the previous move x to square of the previous move.
+
+use std::cell::UnsafeCell;
+
+use crate::mv::Mv;
+use crate::piece::ClrPiece;
+
+// 12 pieces * to sq
+const TABLE_SIZE: usize = 12 * 64;
+
+thread_local! { static COUNTER: UnsafeCell<[Mv; TABLE_SIZE]> = const {UnsafeCell::new([Mv::null(); TABLE_SIZE])}}
+
+pub fn set(prev_piece: ClrPiece, prev_mv: &Mv, m: Mv) {
+    let index = index(prev_piece, prev_mv);
+    COUNTER.with(|counter| unsafe {
+        let counter = &mut *counter.get();
+        counter[index] = m;
+    })
+}
+
+/// Returns Mv::null() if there is no counter move saved
+pub fn get(prev_piece: ClrPiece, prev_mv: &Mv) -> Mv {
+    let index = index(prev_piece, prev_mv);
+    COUNTER.with(|counter| unsafe {
+        let counter = &*counter.get();
+        counter[index]
+    })
+}
+
+fn index(prev_piece: ClrPiece, prev_mv: &Mv) -> usize {
+    let (_, to) = prev_mv.sq();
+    prev_piece.index() * 64 + to as usize
+}
@@ -1,52 +1,132 @@
 //! ## History Heuristic
 //! The history heuristic evaluates moves based on how often a move was previously evaluated.
-//! The data is saved in a global tables, indexed by: from square x to square x color.
-//! The formula is again different for every engine, rosa currently uses: prev history + depth^2
+//! The data is saved in global (thread local) tables:
+//! - Butterfly history, indexed by: color x from square x to square
+//! - Continuation history, indexed by: (piece, to square) of a previous move x (piece, to square) of the move.
+//!   There is one table for the previous move and one for the move before that
+//! - Capture history, indexed by: moving piece x to square x captured piece
+//!
+//! The formula is again different for every engine, rosa uses gravity: entry += bonus - entry * |bonus| / MAX.
+//! This keeps every entry inside of +-MAX and makes often updated entries less sensitive to single updates.
+//! The move that caused a beta cutoff gets a bonus of depth^2 (bounded),
+//! every move of the same kind that was searched before it gets the same amount as malus.
+//! Move ordering uses the full precision of the tables; quiets are scored by the sum of the butterfly & continuation tables.
+//! Every search thread starts with empty tables.
 
 use std::cell::UnsafeCell;
 
-use crate::piece::Clr;
 use crate::mv::Mv;
+use crate::piece::ClrPiece;
+
+/// The piece that moved & its to square
+pub type PieceTo = (ClrPiece, u8);
+
+pub const MAX_HISTORY: i32 = 16384;
+const MAX_BONUS: i32 = 1536;
 
 // from sq * to sq * 2 colors
-const TABLE_SIZE: usize = 64 * 64 * 2;
-const MAX_HISTORY: u16 = u16::pow(2, 14);
+const BUTTERFLY_SIZE: usize = 64 * 64 * 2;
+// 12 pieces * to sq
+const PIECE_TO_SIZE: usize = 12 * 64;
+const CONTINUATION_SIZE: usize = PIECE_TO_SIZE * PIECE_TO_SIZE;
+// 12 pieces * to sq * 6 victims
+const CAPTURE_SIZE: usize = PIECE_TO_SIZE * 6;
 
-thread_local! { static HISTORY: UnsafeCell<[u16; TABLE_SIZE]> = const {UnsafeCell::new([0; TABLE_SIZE])}}
+thread_local! { static BUTTERFLY: UnsafeCell<[i16; BUTTERFLY_SIZE]> = const {UnsafeCell::new([0; BUTTERFLY_SIZE])}}
+thread_local! { static CAPTURE: UnsafeCell<[i16; CAPTURE_SIZE]> = const {UnsafeCell::new([0; CAPTURE_SIZE])}}
+// Too big for the thread local block
+thread_local! { static CONTINUATION: UnsafeCell<[Box<[i16]>; 2]> = UnsafeCell::new([
+    vec![0; CONTINUATION_SIZE].into_boxed_slice(),
+    vec![0; CONTINUATION_SIZE].into_boxed_slice(),
+])}
 
-pub fn set(m: &Mv, clr: Clr, depth: u8) {
-    let (from, to) = m.sq();
-    let index = index(from, to, clr);
-    HISTORY.with(|history| unsafe {
-        let history = &mut *history.get();
-        let depth = depth as u16;
-        history[index] = u16::clamp(history[index] + depth * depth, 0, MAX_HISTORY - 1);
-    })
+pub fn bonus(depth: u8) -> i32 {
+    let depth = depth as i32;
+    i32::min(depth * depth * 32, MAX_BONUS)
 }
 
-pub fn get(m: &Mv, clr: Clr) -> u32 {
-    let (from, to) = m.sq();
-    let index = index(from, to, clr);
-    let raw_val = HISTORY.with(|t| unsafe {
-        let history = &mut *t.get();
-        history[index]
+/// cont: The last & second to last move (None after null moves & at the root)
+pub fn quiet_score(m: &Mv, piece: ClrPiece, cont: &[Option<PieceTo>; 2]) -> i32 {
+    let mut score = BUTTERFLY.with(|t| unsafe { (*t.get())[butterfly_index(m, piece)] }) as i32;
+    CONTINUATION.with(|t| unsafe {
+        let tables = &*t.get();
+        for (table, prev) in tables.iter().zip(cont) {
+            if let Some(prev) = prev {
+                score += table[continuation_index(prev, m, piece)] as i32;
+            }
+        }
+    });
+    score
+}
+
+pub fn update_quiet(m: &Mv, piece: ClrPiece, cont: &[Option<PieceTo>; 2], bonus: i32) {
+    BUTTERFLY.with(|t| unsafe {
+        let table = &mut *t.get();
+        gravity(&mut table[butterfly_index(m, piece)], bonus);
+    });
+    CONTINUATION.with(|t| unsafe {
+        let tables = &mut *t.get();
+        for (table, prev) in tables.iter_mut().zip(cont) {
+            if let Some(prev) = prev {
+                gravity(&mut table[continuation_index(prev, m, piece)], bonus);
+            }
+        }
     });
+}
 
-    if raw_val == 0 {
+/// 0 for non captures
+pub fn capture_score(m: &Mv, piece: ClrPiece) -> i32 {
+    if !m.is_cap() {
         return 0;
     }
+    CAPTURE.with(|t| unsafe { (*t.get())[capture_index(m, piece)] }) as i32
+}
+
+pub fn update_capture(m: &Mv, piece: ClrPiece, bonus: i32) {
+    if !m.is_cap() {
+        return;
+    }
+    CAPTURE.with(|t| unsafe {
+        let table = &mut *t.get();
+        gravity(&mut table[capture_index(m, piece)], bonus);
+    });
+}
+
+/// Clears all tables of the calling thread
+pub fn clear() {
+    BUTTERFLY.with(|t| unsafe { (*t.get()).fill(0) });
+    CAPTURE.with(|t| unsafe { (*t.get()).fill(0) });
+    CONTINUATION.with(|t| unsafe {
+        for table in (*t.get()).iter_mut() {
+            table.fill(0);
+        }
+    });
+}
 
-    // This needs to map into 5 bit -> Linear scaling
-    let res = (raw_val as u32 * 31) / MAX_HISTORY as u32;
-    debug_assert!(res < 32);
-    res
+fn gravity(entry: &mut i16, bonus: i32) {
+    let bonus = bonus.clamp(-MAX_HISTORY, MAX_HISTORY);
+    let val = *entry as i32;
+    *entry = (val + bonus - val * bonus.abs() / MAX_HISTORY) as i16;
 }
 
-fn index(from: u8, to: u8, clr: Clr) -> usize {
+fn butterfly_index(m: &Mv, piece: ClrPiece) -> usize {
+    let (from, to) = m.sq();
     let mut clr_bonus = 0;
-    if clr.is_black() {
+    if piece.clr().is_black() {
         clr_bonus = 64 * 64;
     }
 
     (from as usize * 64 + to as usize) + clr_bonus
 }
+
+fn piece_to_index(piece: ClrPiece, to: u8) -> usize {
+    piece.index() * 64 + to as usize
+}
+
+fn continuation_index(prev: &PieceTo, m: &Mv, piece: ClrPiece) -> usize {
+    piece_to_index(prev.0, prev.1) * PIECE_TO_SIZE + piece_to_index(piece, m.sq().1)
+}
+
+fn capture_index(m: &Mv, piece: ClrPiece) -> usize {
+    piece_to_index(piece, m.sq().1) * 6 + (m.cap_victim().val() - 1) as usize
+}
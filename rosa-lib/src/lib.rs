@@ -1,9 +1,11 @@
 pub mod board;
+pub mod counter;
 pub mod history;
 pub mod mv;
 pub mod mvvlva;
 pub mod piece;
 pub mod pos;
+pub mod score;
 pub mod tt;
 pub mod util;
 
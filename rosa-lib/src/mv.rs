@@ -1,6 +1,5 @@
 //! # Move Representation
 
-use crate::history;
 use crate::mvvlva;
 use crate::piece::*;
 use crate::pos;
@@ -54,7 +53,8 @@ const BK_STARTING_SQ: u8 = 60;
 ///                  Old  
 ///                  is ep  
 ///  </pre>
-///  The score value is either the mvvlva score for captures or history heuristic for non captures
+///  The score value is the mvvlva score for captures and 0 for non captures
+///  (History scores need more precision, the move picker keeps them outside of the move)
 ///  We add 32 to the mvvlva score to a) mv order them higher and b) the very first bit becomes a is_cap() bit  
 ///  We also need to save the old en passant & castling data for unmake()
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -84,11 +84,9 @@ impl Mv {
         Mv(val)
     }
 
-    pub fn new_quiet(start: u8, end: u8, clr: Clr) -> Mv {
+    pub fn new_quiet(start: u8, end: u8) -> Mv {
         let mut mv = Mv::new_mv(start, end);
         mv.set_flag(Flag::Quiet);
-        let score = history::get(&mv, clr);
-        mv.set_score(score);
         mv
     }
 
@@ -181,6 +179,11 @@ impl Mv {
             _ => None,
         };
 
+        Mv::from_parts(start, end, prom_piece, p)
+    }
+
+    /// The flags are derived from the pieces in the position
+    fn from_parts(start: u8, end: u8, prom_piece: Option<Piece>, p: &Pos) -> Mv {
         let piece = p.piece_at_sq(start).unwrap();
         let op_piece = p.piece_at_sq(end);
 
@@ -220,7 +223,7 @@ impl Mv {
                 } else if piece.de_clr() == Piece::Pawn && (mv_diff == 7 || mv_diff == 9) {
                     Mv::new_ep(start, end)
                 } else {
-                    Mv::new_quiet(start, end, p.clr())
+                    Mv::new_quiet(start, end)
                 }
             }
         }
@@ -232,6 +235,10 @@ impl Mv {
         Mv(0)
     }
 
+    pub fn is_null(&self) -> bool {
+        self.0 == 0
+    }
+
     pub fn sq(&self) -> (u8, u8) {
         (
             ((self.0 & START) >> START_OFFSET) as u8,
@@ -239,6 +246,13 @@ impl Mv {
         )
     }
 
+    /// Compares only the parts that identify a move in a position (start, end, flag & prom piece)
+    /// Score and the saved unmake data are ignored
+    pub fn fuzzy_compare(&self, other: &Mv) -> bool {
+        const IDENTITY: u32 = START | END | FLAG | PROM_PIECE;
+        self.0 & IDENTITY == other.0 & IDENTITY
+    }
+
     pub fn is_prom(&self) -> bool {
         matches!(self.flag(), Flag::Prom | Flag::PromCap)
     }
@@ -309,12 +323,41 @@ impl Mv {
         self.capture_data().1
     }
 
-    // You could also sanitize history heuristic, but since thats only relevant
-    // for move ordering we can ignore it here
-    pub fn sanitize_tt(&mut self) {
-        self.0 &= !OLD_IS_EP;
-        self.0 &= !OLD_CASTLE;
-        self.0 &= !OLD_EP_FILE;
+    /// Start, end & promotion piece; the rest of the move can be restored from the position
+    /// <pre>
+    ///   Is Prom
+    ///     | Prom   Start   End
+    ///     | Piece    |      |
+    ///     | ||    |-----||-----|
+    ///  0b0000_0000_0000_0000
+    /// </pre>
+    pub fn compact(&self) -> u16 {
+        let mut val = (self.0 & (START | END)) as u16;
+        if !self.is_null() && self.is_prom() {
+            val |= 1 << 14;
+            val |= (((self.0 & PROM_PIECE) >> PROM_OFFSET) as u16) << 12;
+        }
+        val
+    }
+
+    /// The null move if there is no piece on the start square or a king on the end square
+    /// (the entry belongs to another position)
+    /// The move is not checked for (pseudo) legality
+    pub fn from_compact(val: u16, p: &Pos) -> Mv {
+        let start = ((val as u32 & START) >> START_OFFSET) as u8;
+        let end = (val as u32 & END) as u8;
+        let prom_piece = if val & (1 << 14) != 0 {
+            Some(Piece::decompress_prom(((val >> 12) & 0b11) as u32))
+        } else {
+            None
+        };
+        let king_victim = p
+            .piece_at_sq(end)
+            .is_some_and(|piece| piece.de_clr() == Piece::King);
+        if val == 0 || p.piece_at_sq(start).is_none() || king_victim {
+            return Mv::null();
+        }
+        Mv::from_parts(start, end, prom_piece, p)
     }
 
     pub fn set_old_castle_rights(&mut self, rights: pos::Castling) {
@@ -7,6 +7,10 @@
 //! (Bitboards for move generation, piece tables for checking for checks & promotions)
 //! Since this struct is only constructed once you dont need to particuarly optimize for memory layout.
 //! Instead speed of access and storing is key.
+//! ## History
+//! The keys of all previous positions are saved for repetition detection.
+//! A position can only repeat since the last irreversible move (capture, pawn move or null move),
+//! so every entry also saves how many plies ago that was.
 
 use crate::board::Board;
 use crate::piece::*;
@@ -27,7 +31,10 @@ pub struct Pos {
     // Using the consts defined above
     sq: [ClrPieceOption; 64],
 
-    pub repetition: Vec<tt::Key>,
+    /// The current position is the last entry (including null moves)
+    repetition: Vec<tt::Key>,
+    /// Plies since the last irreversible move, one entry per key
+    reversible: Vec<u16>,
 
     key: tt::Key,
 
@@ -46,11 +53,7 @@ pub struct Castling {
 
 impl Pos {
     pub fn new(
-        sq: [ClrPieceOption; 64],
-        clr: Clr,
-        is_ep: bool,
-        ep_file: u8,
-        castle: Castling,
+        sq: [ClrPieceOption; 64], clr: Clr, is_ep: bool, ep_file: u8, castle: Castling,
     ) -> Pos {
         let mut boards = [Board::new(); 12];
         for (sq, piece) in sq.into_iter().enumerate() {
@@ -68,11 +71,12 @@ impl Pos {
             key: tt::Key::default(),
             ep: is_ep.then(|| ep_file),
             repetition: Vec::with_capacity(20),
+            reversible: Vec::with_capacity(20),
         };
 
         newp.gen_new_full();
         newp.gen_new_key();
-        newp.repetition.push(newp.key);
+        newp.push_history(true);
         newp
     }
 
@@ -127,20 +131,58 @@ impl Pos {
         self.key.piece(sq, piece);
     }
 
-    pub fn repetitions(&self) -> u8 {
-        // Check for repetitions
-        // Null moves are added, so we can only check every second (same color)
+    /// Called after every move with the new key
+    /// irreversible: Capture, pawn move or null move
+    pub fn push_history(&mut self, irreversible: bool) {
+        let plies = if irreversible {
+            0
+        } else {
+            self.reversible
+                .last()
+                .map_or(0, |plies| plies.saturating_add(1))
+        };
+        self.repetition.push(self.key);
+        self.reversible.push(plies);
+    }
+
+    pub fn pop_history(&mut self) {
+        self.repetition.pop();
+        self.reversible.pop();
+    }
+
+    /// The halfmove clock of the fen (only the root knows positions before it)
+    pub fn set_halfmove_clock(&mut self, plies: u16) {
+        if let Some(last) = self.reversible.last_mut() {
+            *last = plies;
+        }
+    }
+
+    /// How far back a repetition can be, bounded by the known history
+    pub fn reversible_plies(&self) -> usize {
+        let plies = self.reversible.last().copied().unwrap_or(0) as usize;
+        plies.min(self.repetition.len().saturating_sub(1))
+    }
+
+    /// Only valid for plies <= reversible_plies()
+    pub fn key_before(&self, plies: usize) -> tt::Key {
+        self.repetition[self.repetition.len() - 1 - plies]
+    }
+
+    /// ply: Distance from the root
+    /// A position that already occurred after the root is a draw (the side to move can repeat again),
+    /// positions before the root have to occur twice (threefold repetition)
+    pub fn is_repetition(&self, ply: usize) -> bool {
         let mut rep_count = 0;
-        // Start at the previous same-side position (current is last entry)
-        for key in self.repetition.iter().rev().skip(2).step_by(2) {
-            if *key == self.key() {
-                if rep_count >= 2 {
-                    return 3;
+        // The same side has to be to move & it takes at least 4 plies to get back
+        for plies in (4..=self.reversible_plies()).step_by(2) {
+            if self.key_before(plies) == self.key {
+                if plies <= ply || rep_count >= 1 {
+                    return true;
                 }
                 rep_count += 1;
             }
         }
-        rep_count
+        false
     }
 
     pub fn piece_iter(&self) -> impl Iterator<Item = ClrPieceOption> {
@@ -278,7 +320,7 @@ impl std::fmt::Display for Pos {
         board += format!("To move: {}\n", self.clr).as_str();
         board += format!("Castling right: {:?}\n", self.castle()).as_str();
         board += format!("En passant file: {:?}\n", self.ep()).as_str();
-        board += format!("Repetition draw: {}\n", self.repetitions()).as_str();
+        board += format!("Repetition draw: {}\n", self.is_repetition(0)).as_str();
         write!(f, "{}", board)
     }
 }
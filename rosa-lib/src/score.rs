@@ -0,0 +1,115 @@
+//! ## Score
+//! Scores are in centipawns from the view of the side to move.
+//! Mates are encoded by their distance in plies from the root:
+//! a mate in `ply` plies is MATE - ply, being mated in `ply` plies is -MATE + ply.
+//! This way a faster mate always has a better score than a slower one.
+//! ### Mate scores in the TT
+//! The TT is shared between different plies (& different searches), so mate scores are saved
+//! relative to the node instead of the root: score.to_tt(ply) & Score::from_tt(val, ply).
+//! All scores fit into an i16, which is what the TT saves.
+
+use std::fmt;
+use std::ops::{Add, Neg, Sub};
+
+const MATE: i32 = 32000;
+/// Every mate has to be found within this amount of plies
+const MAX_MATE_PLY: i32 = 1000;
+const MATE_BOUND: i32 = MATE - MAX_MATE_PLY;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Debug)]
+pub struct Score(i32);
+
+impl Score {
+    /// Bigger than any real score (used for the search window)
+    pub const INFINITY: Score = Score(MATE + 1);
+    pub const DRAW: Score = Score(0);
+
+    /// Static evaluations, clamped so they cant be mistaken for mates
+    pub fn cp(val: i32) -> Score {
+        Score(val.clamp(-MATE_BOUND + 1, MATE_BOUND - 1))
+    }
+
+    /// The side to move mates in ply plies (from the root)
+    pub fn mate_in(ply: usize) -> Score {
+        Score(MATE - ply as i32)
+    }
+
+    /// The side to move is mated in ply plies (from the root)
+    pub fn mated_in(ply: usize) -> Score {
+        Score(-MATE + ply as i32)
+    }
+
+    pub fn val(&self) -> i32 {
+        self.0
+    }
+
+    pub fn is_mate(&self) -> bool {
+        self.0.abs() >= MATE_BOUND && self.0.abs() <= MATE
+    }
+
+    /// Full moves until mate as used by uci: positive if the side to move mates
+    pub fn mate_moves(&self) -> Option<i32> {
+        if !self.is_mate() {
+            return None;
+        }
+        if self.0 > 0 {
+            Some((MATE - self.0 + 1) / 2)
+        } else {
+            Some(-(MATE + self.0) / 2)
+        }
+    }
+
+    /// Mates relative to the node at ply
+    pub fn to_tt(self, ply: usize) -> i32 {
+        let ply = ply as i32;
+        if self.0 >= MATE_BOUND {
+            self.0 + ply
+        } else if self.0 <= -MATE_BOUND {
+            self.0 - ply
+        } else {
+            self.0
+        }
+    }
+
+    pub fn from_tt(val: i32, ply: usize) -> Score {
+        let ply = ply as i32;
+        if val >= MATE_BOUND {
+            Score(val - ply)
+        } else if val <= -MATE_BOUND {
+            Score(val + ply)
+        } else {
+            Score(val)
+        }
+    }
+}
+
+impl Neg for Score {
+    type Output = Score;
+    fn neg(self) -> Score {
+        Score(-self.0)
+    }
+}
+
+impl Add<i32> for Score {
+    type Output = Score;
+    fn add(self, rhs: i32) -> Score {
+        Score(self.0 + rhs)
+    }
+}
+
+impl Sub<i32> for Score {
+    type Output = Score;
+    fn sub(self, rhs: i32) -> Score {
+        Score(self.0 - rhs)
+    }
+}
+
+/// Uci notation: "cp 35" or "mate -3"
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.mate_moves() {
+            Some(moves) => write!(f, "mate {moves}"),
+            None => write!(f, "cp {}", self.0),
+        }
+    }
+}
@@ -1,90 +1,370 @@
 //! # Transposition Table
+//! The table is split into buckets, each the size of a cache line (64 bytes),
+//! so a lookup only ever touches a single cache line.
+//! Every bucket holds 8 entries, each packed into a single u64.
+//! ## Entry Layout
+//! From least to most significant bits:
+//! - Key check: 16 bits
+//! - Move: 16 bits
+//! - Score: i16 -> 16 bits
+//! - Depth: 8 bits
+//! - Bound (node type): 2 bits
+//! - Generation: 6 bits
+//!
+//! The bucket is selected by the upper bits of the key, the lower 16 bits are saved in the entry to verify it.
+//! With 16 bits two positions in the same bucket produce the same check once in a while,
+//! so the tt move has to be validated before it is played.  
+//! The move only saves start, end & promotion piece; the rest is restored from the position.
+//! Scores have to fit into an i16; mate scores are saved relative to the node (see score).
+//! ## Replacement
+//! An entry for the same position is overwritten, unless it is from the current search and a lot deeper.
+//! Otherwise the least valuable entry of the bucket is replaced: depth - 8 * age,
+//! where the age is the amount of searches (generations) since the entry was written.
+//! This way old entries from previous searches / games dont block the table forever.
 //! ## Safety
-//! The is no read write write - reading might produce smeared data
+//! The table is shared by all search threads without any locking.
+//! Every entry is a single atomic word, so a read never sees half of a write.
+//! Two racing stores can overwrite each other (one entry is lost), which is fine for a tt.
+//! A probe only accepts an entry if its key check matches, so a racing store for another position
+//! in the same bucket can never be mistaken for our entry.
+//! This is checked with a loom model of concurrent stores & probes (tests/tt_loom.rs):
+//! `RUSTFLAGS="--cfg loom" cargo test -p rosa-lib --test tt_loom --release`
+//! ## Persistence
+//! The table can be saved to & loaded from a file, so a deep analysis can be continued later.
+//! The file consists of a header (format version, zobrist seed & checksum, size, generation),
+//! followed by every entry as a little endian u64.
+//! A file can only be loaded into a table of the same size, loading never resizes the table.
+//! Entries are only meaningful with the exact same zobrist keys, which is why the keys are generated from a fixed seed.
 //! ## Zobrist Hashing
 
 use crate::mv::Mv;
 use crate::piece::*;
 use crate::pos;
 
-use rand::RngCore;
+#[cfg(loom)]
+use loom::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+use rand::{RngCore, SeedableRng};
+use rand_pcg::Pcg64;
 use std::cell::UnsafeCell;
+use std::io::{self, Read, Write};
+#[cfg(not(loom))]
+use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+
+/// Bytes used per entry
+pub const ENTRY_SIZE: u64 = std::mem::size_of::<u64>() as u64;
+const BUCKET_ENTRIES: usize = 8;
+/// Entries that are looked at for the hashfull estimate
+const HASHFULL_SAMPLE: usize = 1000;
+
+pub const ZOBRIST_SEED: u64 = 0x524F_5341_4348_4553;
+const FILE_MAGIC: &[u8; 8] = b"ROSA-TT\0";
+/// Has to be increased whenever the entry layout changes
+const FORMAT_VERSION: u32 = 1;
+
+#[repr(align(64))]
+#[derive(Default)]
+struct Bucket([AtomicU64; BUCKET_ENTRIES]);
 
 #[derive(Default)]
 pub struct TT {
-    table: UnsafeCell<Vec<Option<Entry>>>,
+    table: UnsafeCell<Vec<Bucket>>,
+    generation: AtomicU8,
 }
 
+// The entries are atomics, only resize() changes the table itself
 unsafe impl Sync for TT {}
 
 impl TT {
+    #[cfg(not(loom))]
     pub const fn new() -> TT {
         TT {
             table: UnsafeCell::new(Vec::new()),
+            generation: AtomicU8::new(0),
         }
     }
 
-    pub fn resize(&self, size: u64) {
-        unsafe {
-            (*self.table.get()).resize(size as usize, None);
+    /// Loom atomics can only be created inside of a model
+    #[cfg(loom)]
+    pub fn new() -> TT {
+        TT {
+            table: UnsafeCell::new(Vec::new()),
+            generation: AtomicU8::new(0),
         }
     }
 
-    pub fn get(&self, key: Key) -> Option<Entry> {
+    /// Size in entries, rounded down to full buckets. Also clears the table
+    /// # Safety
+    /// No other thread can access the table while it is resized (-> only call this while no search is running)
+    pub unsafe fn resize(&self, size: u64) {
+        let buckets = usize::max(size as usize / BUCKET_ENTRIES, 1);
         unsafe {
-            let index = key.val() % self.size();
-            (&(*self.table.get())).get(index as usize).unwrap().clone()
+            let table = &mut *self.table.get();
+            table.clear();
+            table.shrink_to_fit();
+            table.resize_with(buckets, Bucket::default);
         }
     }
 
-    pub fn set(&self, entry: Entry) {
-        unsafe {
-            let index = entry.key.val() % self.size();
-            (&mut (*self.table.get()))[index as usize] = Some(entry);
+    /// Removes all entries & resets the generation
+    pub fn clear(&self) {
+        let table = unsafe { &*self.table.get() };
+        for slot in table.iter().flat_map(|bucket| &bucket.0) {
+            slot.store(0, Ordering::Relaxed);
+        }
+        self.generation.store(0, Ordering::Relaxed);
+    }
+
+    /// Has to be called at the start of every search, so old entries age
+    pub fn new_search(&self) {
+        let generation = (self.generation() + 1) & GENERATION_MASK;
+        self.generation.store(generation, Ordering::Relaxed);
+    }
+
+    /// Only returns entries that were saved for this key (see module doc for collisions)
+    pub fn get(&self, key: Key) -> Option<Entry> {
+        let check = key.check();
+        self.bucket(key)
+            .0
+            .iter()
+            .map(|slot| slot.load(Ordering::Relaxed))
+            .find(|&data| data != 0 && data as u16 == check)
+            .map(|data| Entry::unpack(key, data))
+    }
+
+    pub fn set(&self, mut entry: Entry) {
+        let generation = self.generation();
+        let check = entry.key.check();
+        let bucket = self.bucket(entry.key);
+
+        let mut replace = 0;
+        let mut worst = i32::MAX;
+        for (i, slot) in bucket.0.iter().enumerate() {
+            let data = slot.load(Ordering::Relaxed);
+            if data == 0 {
+                if worst > i32::MIN {
+                    replace = i;
+                    worst = i32::MIN;
+                }
+                continue;
+            }
+
+            let old = Entry::unpack(entry.key, data);
+            if data as u16 == check {
+                if old.generation == generation
+                    && entry.depth as i32 + 2 < old.depth as i32
+                    && entry.node_type != EntryType::Exact
+                {
+                    return;
+                }
+                // Dont lose the move of a previous search of this position
+                if entry.compact_mv == 0 {
+                    entry.compact_mv = old.compact_mv;
+                }
+                replace = i;
+                break;
+            }
+
+            let age = generation.wrapping_sub(old.generation) & GENERATION_MASK;
+            let worth = old.depth as i32 - 8 * age as i32;
+            if worth < worst {
+                replace = i;
+                worst = worth;
+            }
         }
+
+        entry.generation = generation;
+        bucket.0[replace].store(entry.pack(), Ordering::Relaxed);
     }
 
+    /// Size in entries
     pub fn size(&self) -> u64 {
-        unsafe { (*self.table.get()).len() as u64 }
+        unsafe { (*self.table.get()).len() as u64 * BUCKET_ENTRIES as u64 }
     }
 
-    /// Non null entries & Table Size
-    pub fn load_factor(&self) -> (u64, u64) {
-        let mut entry_count = 0;
-        for index in unsafe { 0..(*self.table.get()).len() } {
-            if unsafe { (&(*self.table.get())).get(index).unwrap().is_some() } {
-                entry_count += 1;
+    /// Permille of the sampled entries that were written during the current search (uci hashfull)
+    pub fn hashfull(&self) -> u64 {
+        let generation = self.generation();
+        let table = unsafe { &*self.table.get() };
+        let (mut sampled, mut used) = (0, 0);
+        for slot in table
+            .iter()
+            .flat_map(|bucket| &bucket.0)
+            .take(HASHFULL_SAMPLE)
+        {
+            let data = slot.load(Ordering::Relaxed);
+            sampled += 1;
+            if data != 0 && Entry::unpack(Key(0), data).generation == generation {
+                used += 1;
+            }
+        }
+        used * 1000 / u64::max(sampled, 1)
+    }
+
+    /// Writes the header & all entries
+    pub fn save(&self, w: &mut impl Write) -> io::Result<()> {
+        w.write_all(FILE_MAGIC)?;
+        w.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        w.write_all(&ZOBRIST_SEED.to_le_bytes())?;
+        w.write_all(&zobrist_checksum().to_le_bytes())?;
+        w.write_all(&self.size().to_le_bytes())?;
+        w.write_all(&[self.generation()])?;
+
+        let table = unsafe { &*self.table.get() };
+        for slot in table.iter().flat_map(|bucket| &bucket.0) {
+            w.write_all(&slot.load(Ordering::Relaxed).to_le_bytes())?;
+        }
+        w.flush()
+    }
+
+    /// Replaces the entries with the ones saved in the file, which has to be of the same size as the table
+    /// (the table is not resized, so it keeps the size set by the user).
+    /// Files of a different format, with different zobrist keys, a different size
+    /// or a body that does not match the size in the header are refused (InvalidData).
+    /// The entries are read straight into the table, so no second table is allocated.
+    /// If the header is refused the table is untouched, a broken body leaves an empty table
+    /// # Safety
+    /// Same as resize()
+    pub unsafe fn load(&self, r: &mut impl Read) -> io::Result<()> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        let mut magic = [0; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != FILE_MAGIC {
+            return Err(invalid("not a rosa tt file"));
+        }
+        let version = read_u32(r)?;
+        if version != FORMAT_VERSION {
+            return Err(invalid(&format!(
+                "format version {version}, expected {FORMAT_VERSION}"
+            )));
+        }
+        if read_u64(r)? != ZOBRIST_SEED || read_u64(r)? != zobrist_checksum() {
+            return Err(invalid("zobrist keys do not match"));
+        }
+        let size = read_u64(r)?;
+        if size != self.size() {
+            return Err(invalid(&format!(
+                "table size {size}, expected {} (set Hash to the size of the file)",
+                self.size()
+            )));
+        }
+        let mut generation = [0];
+        r.read_exact(&mut generation)?;
+
+        let table = unsafe { &*self.table.get() };
+        let mut body = || {
+            for slot in table.iter().flat_map(|bucket| &bucket.0) {
+                slot.store(read_u64(r)?, Ordering::Relaxed);
             }
+            // Trailing data
+            if r.read(&mut [0])? != 0 {
+                return Err(invalid("more entries than the size in the header"));
+            }
+            Ok(())
+        };
+        if let Err(e) = body() {
+            self.clear();
+            return Err(match e.kind() {
+                io::ErrorKind::UnexpectedEof => invalid("less entries than the size in the header"),
+                _ => e,
+            });
         }
-        (entry_count, self.size())
+        self.generation
+            .store(generation[0] & GENERATION_MASK, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn generation(&self) -> u8 {
+        self.generation.load(Ordering::Relaxed)
     }
+
+    fn bucket(&self, key: Key) -> &Bucket {
+        unsafe {
+            let table = &*self.table.get();
+            // Maps the key onto the table without a modulo, uses the upper bits of the key
+            let index = (key.val() as u128 * table.len() as u128) >> 64;
+            &table[index as usize]
+        }
+    }
+}
+
+fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
 }
 
-/// Alignment:
-/// Key: u64 -> 8 bytes
-/// Score: i32 -> 4 bytes
-/// Mv: Option<NonZeroU32> -> 4 bytes
-/// Depth: u8 -> 1 byte
-/// Node_type: u8 -> 1 byte
-/// Total: 18 bytes (This should probably be used better since its aligned to 24)
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+const MV_OFFSET: u64 = 16;
+const SCORE_OFFSET: u64 = 32;
+const DEPTH_OFFSET: u64 = 48;
+const TYPE_OFFSET: u64 = 56;
+const GENERATION_OFFSET: u64 = 58;
+const GENERATION_MASK: u8 = 0b11_1111;
+
+/// Only the key check, not the full key is saved (see module doc)
 #[derive(Clone)]
 pub struct Entry {
     pub key: Key,
+    /// See Score::to_tt()
     pub score: i32,
-    pub mv: Mv,
+    /// See Mv::compact(); use mv() to get the full move
+    pub compact_mv: u16,
     pub depth: u8,
     pub node_type: EntryType,
+    generation: u8,
 }
 
 impl Entry {
-    pub fn new(key: Key, score: i32, mut mv: Mv, depth: u8, node_type: EntryType) -> Entry {
-        mv.sanitize_tt();
+    pub fn new(key: Key, score: i32, mv: Mv, depth: u8, node_type: EntryType) -> Entry {
         Entry {
             key,
             score,
-            mv,
+            compact_mv: mv.compact(),
             depth,
             node_type,
+            generation: 0,
+        }
+    }
+
+    /// Needs the position the entry belongs to; the move still has to be checked for legality
+    pub fn mv(&self, p: &pos::Pos) -> Mv {
+        Mv::from_compact(self.compact_mv, p)
+    }
+
+    fn pack(&self) -> u64 {
+        let node_type: u64 = match self.node_type {
+            EntryType::Upper => 1,
+            EntryType::Lower => 2,
+            EntryType::Exact => 3,
+        };
+        let score = self.score.clamp(-(i16::MAX as i32), i16::MAX as i32) as i16;
+        (self.key.check() as u64)
+            | ((self.compact_mv as u64) << MV_OFFSET)
+            | ((score as u16 as u64) << SCORE_OFFSET)
+            | ((self.depth as u64) << DEPTH_OFFSET)
+            | (node_type << TYPE_OFFSET)
+            | (((self.generation & GENERATION_MASK) as u64) << GENERATION_OFFSET)
+    }
+
+    fn unpack(key: Key, data: u64) -> Entry {
+        let node_type = match (data >> TYPE_OFFSET) & 0b11 {
+            1 => EntryType::Upper,
+            2 => EntryType::Lower,
+            _ => EntryType::Exact,
+        };
+        Entry {
+            key,
+            score: (data >> SCORE_OFFSET) as u16 as i16 as i32,
+            compact_mv: (data >> MV_OFFSET) as u16,
+            depth: (data >> DEPTH_OFFSET) as u8,
+            node_type,
+            generation: (data >> GENERATION_OFFSET) as u8 & GENERATION_MASK,
         }
     }
 }
@@ -146,6 +426,11 @@ impl Key {
         self.val() == 0
     }
 
+    /// The part of the key that is saved in the tt entry
+    fn check(&self) -> u16 {
+        self.0 as u16
+    }
+
     pub fn color(&mut self) {
         self.0 ^= unsafe { BLACK };
     }
@@ -202,8 +487,9 @@ static mut BLACK: u64 = 0;
 static mut EN_PASSANT: [u64; 8] = [0; 8];
 static mut CASTLE: [u64; 4] = [0; 4];
 
+/// The keys are the same on every run (see Persistence)
 pub fn init_zobrist_keys() {
-    let mut rng = rand::rng();
+    let mut rng = Pcg64::seed_from_u64(ZOBRIST_SEED);
     let mut keys = [[0; 64]; 12];
     for i in 0..12 {
         for j in 0..64 {
@@ -241,3 +527,32 @@ pub fn init_zobrist_keys() {
 
     unsafe { BLACK = rng.next_u64() }
 }
+
+/// Fingerprint of all zobrist keys, so saved tables are refused if the key generation ever changes
+pub fn zobrist_checksum() -> u64 {
+    let mut checksum = 0u64;
+    let mut add = |keys: &[u64]| {
+        for key in keys {
+            checksum = checksum.rotate_left(7) ^ key;
+        }
+    };
+    // Copies, since references to the mutable statics are not allowed
+    let (pieces, en_passant, castle, black) = unsafe {
+        (
+            [
+                PAWN, KNIGHT, BISHOP, ROOK, QUEEN, KING, BPAWN, BKNIGHT, BBISHOP, BROOK, BQUEEN,
+                BKING,
+            ],
+            EN_PASSANT,
+            CASTLE,
+            BLACK,
+        )
+    };
+    for keys in pieces.iter() {
+        add(keys);
+    }
+    add(&en_passant);
+    add(&castle);
+    add(&[black]);
+    checksum
+}
@@ -0,0 +1,33 @@
+use rosa_lib::history;
+use rosa_lib::mv::Mv;
+use rosa_lib::piece::ClrPiece;
+
+#[test]
+fn gravity_bounded() {
+    history::clear();
+    let m = Mv::new_quiet(12, 20);
+    let cont = [Some((ClrPiece::BKnight, 45)), None];
+    for _ in 0..1000 {
+        history::update_quiet(&m, ClrPiece::WPawn, &cont, history::bonus(20));
+    }
+    let score = history::quiet_score(&m, ClrPiece::WPawn, &cont);
+    // Butterfly + one continuation table, both close to the cap
+    assert!(score > history::MAX_HISTORY && score <= 2 * history::MAX_HISTORY);
+
+    for _ in 0..1000 {
+        history::update_quiet(&m, ClrPiece::WPawn, &cont, -history::bonus(20));
+    }
+    let score = history::quiet_score(&m, ClrPiece::WPawn, &cont);
+    assert!(score < -history::MAX_HISTORY && score >= -2 * history::MAX_HISTORY);
+}
+
+#[test]
+fn continuation_is_separate() {
+    history::clear();
+    let m = Mv::new_quiet(1, 18);
+    let cont = [Some((ClrPiece::BPawn, 35)), None];
+    history::update_quiet(&m, ClrPiece::WKnight, &cont, history::bonus(4));
+    let with_cont = history::quiet_score(&m, ClrPiece::WKnight, &cont);
+    let without = history::quiet_score(&m, ClrPiece::WKnight, &[None, None]);
+    assert!(with_cont > without && without > 0);
+}
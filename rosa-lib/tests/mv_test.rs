@@ -57,3 +57,15 @@ fn ep() {
     assert_eq!(m.cap_capturer(), Piece::Pawn);
     assert_eq!(m.cap_victim(), Piece::Pawn);
 }
+
+#[test]
+fn fuzzy_compare() {
+    init();
+    let mut m = Mv::new_double(12, 28);
+    let original = m;
+    m.set_old_ep_file(3);
+    m.set_old_is_ep(true);
+    assert!(m.fuzzy_compare(&original));
+    assert!(!m.fuzzy_compare(&Mv::new_double(11, 27)));
+    assert!(!Mv::null().fuzzy_compare(&original));
+}
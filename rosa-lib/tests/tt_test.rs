@@ -0,0 +1,229 @@
+use std::sync::Once;
+
+use rosa_lib::mv::Mv;
+use rosa_lib::mvvlva::init_mvvlva;
+use rosa_lib::piece::Piece;
+use rosa_lib::score::Score;
+use rosa_lib::tt;
+use rosa_lib::tt::{Entry, EntryType, Key, TT};
+
+static INIT: Once = Once::new();
+
+fn init() {
+    INIT.call_once(|| {
+        init_mvvlva();
+        tt::init_zobrist_keys();
+    });
+}
+
+fn new_table(size: u64) -> TT {
+    let tt = TT::new();
+    unsafe { tt.resize(size) };
+    tt
+}
+
+#[test]
+fn round_trip() {
+    init();
+    let tt = new_table(64);
+    let key = Key::new_from(0xDEAD_BEEF_1234_5678);
+    let mv = Mv::new_prom_cap(52, 61, Piece::Queen, Piece::Rook);
+    tt.set(Entry::new(key, -4242, mv, 17, EntryType::Lower));
+
+    let entry = tt.get(key).unwrap();
+    assert_eq!(entry.score, -4242);
+    assert_eq!(entry.depth, 17);
+    assert!(entry.node_type == EntryType::Lower);
+    assert_eq!(entry.compact_mv, mv.compact());
+
+    // Same bucket, different check
+    assert!(tt.get(Key::new_from(key.val() ^ 1)).is_none());
+}
+
+#[test]
+fn mate_scores_by_ply() {
+    let tt = new_table(8);
+    // Mate in 3 plies from the root, found at ply 2 -> mate in 1 ply from the node
+    let mate = Score::mate_in(3);
+    tt.set(Entry::new(
+        Key::new_from(1),
+        mate.to_tt(2),
+        Mv::null(),
+        1,
+        EntryType::Exact,
+    ));
+    let stored = tt.get(Key::new_from(1)).unwrap().score;
+    assert_eq!(Score::from_tt(stored, 2), mate);
+    // The same node reached at ply 4
+    assert_eq!(Score::from_tt(stored, 4), Score::mate_in(5));
+    assert_eq!(Score::mate_in(5).to_string(), "mate 3");
+    assert_eq!(Score::mated_in(4).to_string(), "mate -2");
+    assert_eq!((-Score::mate_in(5)).mate_moves(), Some(-2));
+    assert!(Score::mate_in(7) < Score::mate_in(5));
+}
+
+/// A table of one bucket, so every key competes for the same 8 entries
+#[test]
+fn depth_and_age_replacement() {
+    let tt = new_table(8);
+    let set = |key: u64, depth: u8| {
+        tt.set(Entry::new(
+            Key::new_from(key),
+            key as i32,
+            Mv::null(),
+            depth,
+            EntryType::Exact,
+        ))
+    };
+    for key in 1..=8 {
+        set(key, key as u8 + 10);
+    }
+    // The shallowest entry is replaced
+    set(9, 15);
+    assert!(tt.get(Key::new_from(1)).is_none());
+    assert!((2..=9).all(|key| tt.get(Key::new_from(key)).is_some()));
+
+    // Entries of old searches are replaced before deeper entries of the current search
+    tt.new_search();
+    set(4, 30);
+    set(10, 1);
+    assert!(tt.get(Key::new_from(4)).is_some());
+    assert!(tt.get(Key::new_from(2)).is_none());
+}
+
+/// Every thread writes entries whose data is derived from the key into a single bucket.
+/// Whatever a concurrent read returns has to belong to the key it was read for
+/// (the keys differ in the checked bits, so there are no collisions)
+#[test]
+fn concurrent_entries_are_consistent() {
+    let tt = new_table(8);
+    let data = |key: u64| ((key % 30_000) as i32, (key % 60) as u8);
+    let gen_key = |thread: u64, i: u64| (i << 32) | (thread * 16_000 + i);
+
+    std::thread::scope(|s| {
+        for thread in 0..4u64 {
+            let tt = &tt;
+            s.spawn(move || {
+                for i in 0..16_000u64 {
+                    let key = gen_key(thread, i);
+                    let (score, depth) = data(key);
+                    tt.set(Entry::new(
+                        Key::new_from(key),
+                        score,
+                        Mv::null(),
+                        depth,
+                        EntryType::Exact,
+                    ));
+
+                    let probe = gen_key(thread, i / 2);
+                    if let Some(entry) = tt.get(Key::new_from(probe)) {
+                        assert_eq!((entry.score, entry.depth), data(probe));
+                    }
+                }
+            });
+        }
+    });
+}
+
+#[test]
+fn clear() {
+    let tt = new_table(64);
+    tt.new_search();
+    tt.set(Entry::new(
+        Key::new_from(7),
+        1,
+        Mv::null(),
+        1,
+        EntryType::Exact,
+    ));
+    assert!(tt.hashfull() > 0);
+    tt.clear();
+    assert!(tt.get(Key::new_from(7)).is_none());
+    assert_eq!(tt.hashfull(), 0);
+}
+
+#[test]
+fn save_load() {
+    init();
+    let tt = new_table(64);
+    tt.new_search();
+    let key = Key::new_from(0x1234_5678_9ABC_DEF0);
+    let mv = Mv::new_quiet(12, 28);
+    tt.set(Entry::new(key, 55, mv, 9, EntryType::Upper));
+
+    let mut file = Vec::new();
+    tt.save(&mut file).unwrap();
+
+    let loaded = new_table(64);
+    unsafe { loaded.load(&mut file.as_slice()).unwrap() };
+    let entry = loaded.get(key).unwrap();
+    assert_eq!((entry.score, entry.depth), (55, 9));
+    assert_eq!(entry.compact_mv, mv.compact());
+    assert_eq!(loaded.hashfull(), tt.hashfull());
+}
+
+#[test]
+fn load_refuses_other_keys() {
+    init();
+    let tt = new_table(64);
+    let mut file = Vec::new();
+    tt.save(&mut file).unwrap();
+
+    // The zobrist checksum follows the magic, version & seed
+    file[20] ^= 1;
+    let loaded = new_table(64);
+    loaded.set(Entry::new(
+        Key::new_from(3),
+        1,
+        Mv::null(),
+        1,
+        EntryType::Exact,
+    ));
+    let err = unsafe { loaded.load(&mut file.as_slice()) }.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    // Untouched
+    assert!(loaded.get(Key::new_from(3)).is_some());
+}
+
+#[test]
+fn load_refuses_other_sizes() {
+    init();
+    let tt = new_table(64);
+    let mut file = Vec::new();
+    tt.save(&mut file).unwrap();
+
+    for size in [8, 128] {
+        let loaded = new_table(size);
+        let key = Key::new_from(3);
+        loaded.set(Entry::new(key, 1, Mv::null(), 1, EntryType::Exact));
+        let err = unsafe { loaded.load(&mut file.as_slice()) }.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        // Not resized & untouched
+        assert_eq!(loaded.size(), size);
+        assert!(loaded.get(key).is_some());
+    }
+}
+
+#[test]
+fn load_refuses_broken_files() {
+    init();
+    let tt = new_table(64);
+    let mut file = Vec::new();
+    tt.save(&mut file).unwrap();
+
+    let loaded = new_table(64);
+    let key = Key::new_from(3);
+    let refused = |file: &[u8]| {
+        loaded.set(Entry::new(key, 1, Mv::null(), 1, EntryType::Exact));
+        let err = unsafe { loaded.load(&mut &file[..]) }.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        // The body is read into the table, so a broken one leaves it empty
+        assert_eq!(loaded.size(), 64);
+        assert!(loaded.get(key).is_none());
+    };
+
+    // Truncated
+    refused(&file[..file.len() - 1]);
+    // Trailing data
+    refused(&[file.as_slice(), &[0]].concat());
+}